@@ -0,0 +1,39 @@
+// feeds arbitrary bytes through the same path as a socket: split into chunks,
+// pushed into a MessageReader and the world updates applied to a client world.
+// run with `cargo +nightly fuzz run decode_messages` from the repository root
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use common::*;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte decides how the rest is split up, like reads of different sizes,
+    // and whether the frames have the compression flag
+    let (chunk_size, compressed, data) = match data.split_first() {
+        Some((&n, rest)) => ((n & 0x7f) as usize + 1, n & 0x80 != 0, rest),
+        None => return,
+    };
+    let replication = create_replication();
+    let mut world = World::new();
+    let mut reader = MessageReader::new();
+    reader.set_compressed(compressed);
+    for chunk in data.chunks(chunk_size) {
+        let messages = match reader.push_bytes(chunk) {
+            Ok(messages) => messages,
+            // a real connection is closed here
+            Err(_) => return,
+        };
+        for (msg, _) in messages {
+            // whatever decodes has to encode to a frame that decodes again
+            let frame = encode_message(&msg);
+            assert_eq!(frame.len(), encoded_size(&msg));
+            decode_message(&frame[FRAME_HEADER_SIZE..]).unwrap();
+            let frame = compress_frame(&frame);
+            decode_message(&decompress_payload(&frame[FRAME_HEADER_SIZE..]).unwrap()).unwrap();
+            if let NetworkMessages::WorldUpdate{ updates, .. } | NetworkMessages::WorldState{ updates, .. } = msg {
+                for update in &updates {
+                    replication.apply(&mut world, update);
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,453 @@
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc, mpsc::Receiver, mpsc::TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::Rng;
+
+extern crate common;
+use common::*;
+use tracing::{error, info_span, trace, warn};
+
+
+const USAGE: &str = "usage: bot [--address <host:port>] [--user <name>] [--token <token>] [--encrypt] [--compress] [--bots <count>] [--duration <seconds>] [--spawn-rate <bots per second>]
+           [--pattern random|circle|idle|<script file>] [--fire] [--log-json <file>]
+a script has one step per line: <seconds> <left_right> <up_down> [fire]";
+
+
+#[derive(Debug, Clone, Copy)]
+struct ScriptStep
+{
+    duration: f32,
+    left_right: f32,
+    up_down: f32,
+    fire: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern
+{
+    // a new random direction and speed every few seconds
+    Random,
+    // runs in circles, every bot starts at a different angle
+    Circle,
+    Idle,
+    // the steps are played in a loop
+    Script(Vec<ScriptStep>),
+}
+
+fn parse_script(path: &str) -> Result<Vec<ScriptStep>, String>
+{
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let number = |s: Option<&&str>| s.and_then(|s| s.parse::<f32>().ok()).filter(|v| v.is_finite());
+        match (number(parts.first()), number(parts.get(1)), number(parts.get(2)), parts.get(3)) {
+            (Some(duration), Some(left_right), Some(up_down), fire) if duration > 0.0f32 && matches!(fire, None | Some(&"fire")) && parts.len() <= 4 => {
+                steps.push(ScriptStep { duration, left_right, up_down, fire: fire.is_some() });
+            }
+            _ => return Err(format!("{}:{}: expected <seconds> <left_right> <up_down> [fire]", path, i + 1)),
+        }
+    }
+    if steps.is_empty() {
+        return Err(format!("{} has no steps", path));
+    }
+    Ok(steps)
+}
+
+
+struct Options
+{
+    address: String,
+    // every bot joins as the same user
+    connect: ConnectOptions,
+    bots: usize,
+    duration: Option<f32>,
+    spawn_rate: f32,
+    pattern: Pattern,
+    fire: bool,
+    log_json: Option<String>,
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T, String>
+{
+    let value = value.ok_or_else(|| format!("missing value for {}", name))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+fn parse_args() -> Result<Options, String>
+{
+    let mut options = Options {
+        address: "127.0.0.1:7878".to_string(),
+        connect: ConnectOptions { user: "bot".to_string(), ..Default::default() },
+        bots: 10,
+        duration: None,
+        spawn_rate: 50.0f32,
+        pattern: Pattern::Random,
+        fire: false,
+        log_json: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--address" => options.address = parse_value(&arg, args.next())?,
+            "--user" => options.connect.user = parse_value(&arg, args.next())?,
+            "--token" => options.connect.token = parse_value(&arg, args.next())?,
+            "--encrypt" => options.connect.encrypt = true,
+            "--compress" => options.connect.compress = true,
+            "--bots" => options.bots = parse_value(&arg, args.next())?,
+            "--duration" => options.duration = Some(parse_value(&arg, args.next())?),
+            "--spawn-rate" => {
+                options.spawn_rate = parse_value(&arg, args.next())?;
+                if options.spawn_rate.is_nan() || options.spawn_rate <= 0.0f32 {
+                    return Err("--spawn-rate has to be positive".to_string());
+                }
+            }
+            "--pattern" => {
+                options.pattern = match parse_value::<String>(&arg, args.next())?.as_str() {
+                    "random" => Pattern::Random,
+                    "circle" => Pattern::Circle,
+                    "idle" => Pattern::Idle,
+                    path => Pattern::Script(parse_script(path)?),
+                }
+            }
+            "--fire" => options.fire = true,
+            "--log-json" => options.log_json = Some(parse_value(&arg, args.next())?),
+            "--help" | "-h" => return Err(String::new()),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+
+// shared by all bots and the reporting loop
+struct Shared
+{
+    stats: Mutex<NetStats>,
+    // latest measured round trip of every bot in seconds
+    rtts: Mutex<Vec<Option<f64>>>,
+    connected: AtomicUsize,
+    failed: AtomicUsize,
+    world_updates: AtomicU64,
+    stop: AtomicBool,
+}
+
+
+struct Bot
+{
+    index: usize,
+    writer: MessageWriter<TcpStream>,
+    receiver: Receiver<NetworkMessages>,
+    clock: ClockSync,
+    local_player_id: Option<Entity>,
+    view_tick: u64,
+    recent_inputs: VecDeque<PlayerInput>,
+    sequence_id: u32,
+    fire_timer: f32,
+    settings: ServerSettings,
+    time: f32,
+    // random pattern: current input and when it changes
+    random_input: (f32, f32),
+    random_timer: f32,
+}
+
+impl Bot {
+    fn send(&mut self, msg: &NetworkMessages, shared: &Shared) -> bool
+    {
+        match self.writer.send(msg) {
+            Ok(size) => {
+                shared.stats.lock().unwrap().record_sent(msg, size);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // (left_right, up_down, fire) for the current time
+    fn sample_input(&mut self, pattern: &Pattern, dt: f32) -> (f32, f32, bool)
+    {
+        match pattern {
+            Pattern::Idle => (0.0f32, 0.0f32, true),
+            Pattern::Circle => {
+                let angle = self.time * 2.0f32 + self.index as f32;
+                (angle.cos(), angle.sin(), true)
+            }
+            Pattern::Random => {
+                self.random_timer -= dt;
+                if self.random_timer <= 0.0f32 {
+                    let mut rng = rand::thread_rng();
+                    let angle: f32 = rng.gen_range(0.0f32..std::f32::consts::TAU);
+                    let speed: f32 = rng.gen_range(0.0f32..=1.0f32);
+                    self.random_input = (angle.cos() * speed, angle.sin() * speed);
+                    self.random_timer = rng.gen_range(0.5f32..2.0f32);
+                }
+                (self.random_input.0, self.random_input.1, true)
+            }
+            Pattern::Script(steps) => {
+                let total: f32 = steps.iter().map(|s| s.duration).sum();
+                let mut t = self.time % total;
+                for step in steps {
+                    if t < step.duration {
+                        return (step.left_right, step.up_down, step.fire);
+                    }
+                    t -= step.duration;
+                }
+                let last = steps.last().unwrap();
+                (last.left_right, last.up_down, last.fire)
+            }
+        }
+    }
+
+    // returns false once the connection is gone
+    fn tick(&mut self, dt: f32, options: &Options, shared: &Shared) -> bool
+    {
+        self.time += dt;
+        loop {
+            let msg = match self.receiver.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return false,
+            };
+            match msg {
+                NetworkMessages::WorldState{local_id, tick, settings, ..} => {
+                    self.view_tick = tick;
+                    self.local_player_id = Some(local_id);
+                    self.settings = settings;
+                    if !is_valid_tick_dt(settings.tick_dt) {
+                        warn!("ignoring the invalid tick length {} of the server", settings.tick_dt);
+                        self.settings.tick_dt = TICK_RATE;
+                    }
+                }
+                NetworkMessages::WorldUpdate{tick, ..} => {
+                    self.view_tick = self.view_tick.max(tick);
+                    shared.world_updates.fetch_add(1, Ordering::Relaxed);
+                }
+                NetworkMessages::ClockResponse{client_time, server_receive_time, server_send_time, server_tick, tick_dt} => {
+                    self.clock.handle_response(client_time, server_receive_time, server_send_time, server_tick, tick_dt);
+                    shared.rtts.lock().unwrap()[self.index] = Some(self.clock.rtt());
+                }
+                NetworkMessages::Ping{server_time} if !self.send(&NetworkMessages::Pong{ server_time }, shared) => {
+                    return false;
+                }
+                NetworkMessages::Kicked{reason} => {
+                    warn!("kicked: {}", reason);
+                    return false;
+                }
+                _ => {
+                }
+            }
+        }
+
+        if let Some(request) = self.clock.poll_request() {
+            if !self.send(&request, shared) {
+                return false;
+            }
+        }
+
+        let id = match self.local_player_id {
+            Some(id) => id,
+            None => return true,
+        };
+        let (left_right, up_down, fire) = self.sample_input(&options.pattern, dt);
+        self.sequence_id += 1;
+        if self.recent_inputs.len() == INPUT_REDUNDANCY {
+            self.recent_inputs.pop_front();
+        }
+        self.recent_inputs.push_back(PlayerInput { id, cur_sequence_id: self.sequence_id, left_right, up_down });
+        let msg = NetworkMessages::ClientInput{ inputs: self.recent_inputs.iter().copied().collect() };
+        if !self.send(&msg, shared) {
+            return false;
+        }
+
+        self.fire_timer -= dt;
+        if options.fire && fire && self.fire_timer <= 0.0f32 {
+            let angle: f32 = rand::thread_rng().gen_range(0.0f32..std::f32::consts::TAU);
+            let fire = FireInput { id, cur_sequence_id: self.sequence_id, dir: [angle.cos(), angle.sin()], view_tick: self.view_tick as f64 };
+            if !self.send(&NetworkMessages::ClientFire(fire), shared) {
+                return false;
+            }
+            self.fire_timer = self.settings.fire_cooldown;
+        }
+        true
+    }
+}
+
+fn run_bot(index: usize, options: Arc<Options>, shared: Arc<Shared>)
+{
+    let span = info_span!("bot", index);
+    let _enter = span.enter();
+    let stream = match TcpStream::connect(&options.address) {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("could not connect: {}", e);
+            shared.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    let Connection { mut reader, writer, .. } = match connect_to_server(&stream, &options.connect) {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("could not connect: {}", e);
+            shared.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    let mut read_stream = stream;
+    let (sender, receiver) = mpsc::channel();
+    let reader_shared = shared.clone();
+    let reader_span = span.clone();
+    thread::spawn(move || {
+        let _enter = reader_span.enter();
+        while let Ok(messages) = reader.read_from(&mut read_stream) {
+            for (msg, size) in messages {
+                trace!(message_type = msg.name(), size, "received");
+                reader_shared.stats.lock().unwrap().record_received(&msg, size);
+                if sender.send(msg).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    shared.connected.fetch_add(1, Ordering::Relaxed);
+    let mut bot = Bot {
+        index,
+        writer,
+        receiver,
+        clock: ClockSync::new(),
+        local_player_id: None,
+        view_tick: 0,
+        recent_inputs: VecDeque::new(),
+        sequence_id: 0,
+        fire_timer: 0.0f32,
+        settings: ServerSettings::default(),
+        time: 0.0f32,
+        random_input: (0.0f32, 0.0f32),
+        random_timer: 0.0f32,
+    };
+    let mut timestep = FixedTimestep::new(TICK_RATE);
+    'running: while !shared.stop.load(Ordering::Relaxed) {
+        timestep.update();
+        while timestep.next_tick().is_some() {
+            if !bot.tick(timestep.dt(), &options, &shared) {
+                break 'running;
+            }
+        }
+        timestep.set_dt(bot.clock.tick_dt().unwrap_or(bot.settings.tick_dt));
+        thread::sleep(timestep.time_until_next_step().max(Duration::from_millis(1)));
+    }
+    let _ = bot.writer.stream.shutdown(std::net::Shutdown::Both);
+    shared.connected.fetch_sub(1, Ordering::Relaxed);
+    shared.rtts.lock().unwrap()[index] = None;
+}
+
+
+fn report(shared: &Shared, elapsed: f32, world_updates_per_sec: f32)
+{
+    let mut rtts: Vec<f64> = shared.rtts.lock().unwrap().iter().flatten().copied().collect();
+    rtts.sort_by(|a, b| a.total_cmp(b));
+    let rtt_text = if rtts.is_empty() {
+        "rtt -".to_string()
+    }
+    else {
+        let percentile = |p: f64| rtts[((rtts.len() - 1) as f64 * p).round() as usize] * 1000.0;
+        format!(
+            "rtt min {:.1} avg {:.1} p95 {:.1} max {:.1}ms",
+            percentile(0.0), rtts.iter().sum::<f64>() / rtts.len() as f64 * 1000.0, percentile(0.95), percentile(1.0)
+        )
+    };
+    let mut stats = shared.stats.lock().unwrap();
+    stats.update();
+    println!(
+        "[{:6.1}s] bots {} (failed {}) | in {:.1} KB/s {:.0} msg/s | out {:.1} KB/s {:.0} msg/s | world updates {:.0}/s | {}",
+        elapsed, shared.connected.load(Ordering::Relaxed), shared.failed.load(Ordering::Relaxed),
+        stats.receive_rate.bytes_per_sec / 1024.0f32, stats.receive_rate.messages_per_sec,
+        stats.send_rate.bytes_per_sec / 1024.0f32, stats.send_rate.messages_per_sec,
+        world_updates_per_sec, rtt_text
+    );
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => Arc::new(options),
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = init_logging(options.log_json.as_deref()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let shared = Arc::new(Shared {
+        stats: Mutex::new(NetStats::new()),
+        rtts: Mutex::new(vec![None; options.bots]),
+        connected: AtomicUsize::new(0),
+        failed: AtomicUsize::new(0),
+        world_updates: AtomicU64::new(0),
+        stop: AtomicBool::new(false),
+    });
+
+    let start = Instant::now();
+    let spawner_options = options.clone();
+    let spawner_shared = shared.clone();
+    let spawner = thread::spawn(move || {
+        let mut bots = Vec::new();
+        for index in 0..spawner_options.bots {
+            if spawner_shared.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let options = spawner_options.clone();
+            let shared = spawner_shared.clone();
+            bots.push(thread::spawn(move || run_bot(index, options, shared)));
+            thread::sleep(Duration::from_secs_f32(1.0f32 / spawner_options.spawn_rate));
+        }
+        for bot in bots {
+            let _ = bot.join();
+        }
+    });
+
+    let mut last_report = Instant::now();
+    let mut last_world_updates = 0;
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let elapsed = start.elapsed().as_secs_f32();
+        let world_updates = shared.world_updates.load(Ordering::Relaxed);
+        let per_sec = (world_updates - last_world_updates) as f32 / last_report.elapsed().as_secs_f32();
+        last_world_updates = world_updates;
+        last_report = Instant::now();
+        report(&shared, elapsed, per_sec);
+
+        let done = spawner.is_finished();
+        if done || options.duration.is_some_and(|d| elapsed >= d) {
+            break;
+        }
+    }
+    shared.stop.store(true, Ordering::Relaxed);
+    let _ = spawner.join();
+
+    let stats = shared.stats.lock().unwrap();
+    let elapsed = start.elapsed().as_secs_f32();
+    println!(
+        "done after {:.1}s: received {} msgs ({:.1} KB), sent {} msgs ({:.1} KB), {} failed connections",
+        elapsed, stats.received.messages, stats.received.bytes as f32 / 1024.0f32,
+        stats.sent.messages, stats.sent.bytes as f32 / 1024.0f32, shared.failed.load(Ordering::Relaxed)
+    );
+    for (name, traffic) in &stats.received_by_type {
+        println!("  in  {:<16} {:>10} msgs {:>12.1} KB", name, traffic.messages, traffic.bytes as f32 / 1024.0f32);
+    }
+    for (name, traffic) in &stats.sent_by_type {
+        println!("  out {:<16} {:>10} msgs {:>12.1} KB", name, traffic.messages, traffic.bytes as f32 / 1024.0f32);
+    }
+}
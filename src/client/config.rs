@@ -0,0 +1,67 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use common::ConnectOptions;
+
+
+pub const CLIENT_CONFIG_FILE: &str = "client.toml";
+
+
+// connection settings, every field can be left out of the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig
+{
+    pub address: String,
+    pub user: String,
+    // only checked when the server was started with an auth key
+    pub token: String,
+    pub encrypt: bool,
+    // lz4 compression of larger messages, if the server agrees
+    pub compress: bool,
+    // hex public key of the server, printed by the server on start. Any key is accepted when empty
+    pub server_key: String,
+    // every log event is also appended to this file as JSON, nothing is written when empty
+    pub log_json: String,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            address: "127.0.0.1:7878".to_string(),
+            user: "player".to_string(),
+            token: String::new(),
+            encrypt: false,
+            compress: true,
+            server_key: String::new(),
+            log_json: String::new(),
+        }
+    }
+}
+
+impl ClientConfig {
+    // a missing file gives the defaults. The config is read before logging is set up,
+    // so a broken file is returned as an error for the caller to report
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ClientConfig, String>
+    {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Ok(ClientConfig::default()),
+        };
+        toml::from_str(&text).map_err(|e| format!("could not parse {}: {}", path.display(), e))
+    }
+    pub fn log_file(&self) -> Option<&str>
+    {
+        Some(self.log_json.as_str()).filter(|path| !path.is_empty())
+    }
+    pub fn connect_options(&self) -> ConnectOptions
+    {
+        ConnectOptions {
+            encrypt: self.encrypt,
+            compress: self.compress,
+            server_key: self.server_key.clone(),
+            user: self.user.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
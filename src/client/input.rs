@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use glium::glutin::event::VirtualKeyCode;
+use imgui::{Condition, MouseButton, Ui, Window};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::warn;
+
+
+pub const BINDINGS_FILE: &str = "bindings.toml";
+// stick values below this count as 0, the rest is rescaled so the full range stays reachable
+const AXIS_DEADZONE: f32 = 0.15f32;
+// how far a stick or trigger has to move to be picked up while rebinding
+const CAPTURE_THRESHOLD: f32 = 0.5f32;
+
+// the keys that can be bound, saved by their debug name
+const BINDABLE_KEYS: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+        Up, Down, Left, Right, Space, Return, Tab, Back,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+    ]
+};
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+}
+const ALL_ACTIONS: [Action; 5] = [Action::MoveUp, Action::MoveDown, Action::MoveLeft, Action::MoveRight, Action::Fire];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mouse {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+}
+const ALL_AXES: [PadAxis; 4] = [PadAxis::LeftStickX, PadAxis::LeftStickY, PadAxis::RightStickX, PadAxis::RightStickY];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Select,
+    Start,
+}
+const ALL_BUTTONS: [PadButton; 14] = [
+    PadButton::South, PadButton::East, PadButton::North, PadButton::West,
+    PadButton::LeftBumper, PadButton::RightBumper, PadButton::LeftTrigger, PadButton::RightTrigger,
+    PadButton::DPadUp, PadButton::DPadDown, PadButton::DPadLeft, PadButton::DPadRight,
+    PadButton::Select, PadButton::Start,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisDir {
+    Positive,
+    Negative,
+}
+
+// one physical input, every binding gives a value in [0, 1]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    Key(#[serde(serialize_with = "serialize_key", deserialize_with = "deserialize_key")] VirtualKeyCode),
+    Mouse(Mouse),
+    PadButton(PadButton),
+    // one half of a stick axis, up and right are positive
+    PadAxis(PadAxis, AxisDir),
+}
+
+fn serialize_key<S: Serializer>(key: &VirtualKeyCode, s: S) -> Result<S::Ok, S::Error>
+{
+    s.serialize_str(&format!("{:?}", key))
+}
+fn deserialize_key<'de, D: Deserializer<'de>>(d: D) -> Result<VirtualKeyCode, D::Error>
+{
+    let name = String::deserialize(d)?;
+    BINDABLE_KEYS.iter().find(|k| format!("{:?}", k) == name).copied()
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown key {}", name)))
+}
+
+impl Binding {
+    pub fn name(&self) -> String
+    {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Mouse(button) => format!("Mouse {:?}", button),
+            Binding::PadButton(button) => format!("Pad {:?}", button),
+            Binding::PadAxis(axis, AxisDir::Positive) => format!("Pad {:?}+", axis),
+            Binding::PadAxis(axis, AxisDir::Negative) => format!("Pad {:?}-", axis),
+        }
+    }
+    fn value(&self, ui: &Ui, pad: &PadState) -> f32
+    {
+        let io = ui.io();
+        match *self {
+            Binding::Key(key) => {
+                if io.want_capture_keyboard || !io.keys_down[key as usize] { 0.0f32 } else { 1.0f32 }
+            }
+            Binding::Mouse(button) => {
+                let button = match button {
+                    Mouse::Left => MouseButton::Left,
+                    Mouse::Right => MouseButton::Right,
+                    Mouse::Middle => MouseButton::Middle,
+                };
+                if io.want_capture_mouse || !ui.is_mouse_down(button) { 0.0f32 } else { 1.0f32 }
+            }
+            Binding::PadButton(button) => pad.buttons[button as usize],
+            Binding::PadAxis(axis, dir) => {
+                let v = pad.axes[axis as usize];
+                let v = if dir == AxisDir::Positive { v } else { -v };
+                ((v - AXIS_DEADZONE) / (1.0f32 - AXIS_DEADZONE)).clamp(0.0f32, 1.0f32)
+            }
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bindings
+{
+    pub actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut actions = BTreeMap::new();
+        actions.insert(Action::MoveUp, vec![
+            Binding::Key(VirtualKeyCode::W), Binding::Key(VirtualKeyCode::Up),
+            Binding::PadAxis(PadAxis::LeftStickY, AxisDir::Positive), Binding::PadButton(PadButton::DPadUp),
+        ]);
+        actions.insert(Action::MoveDown, vec![
+            Binding::Key(VirtualKeyCode::S), Binding::Key(VirtualKeyCode::Down),
+            Binding::PadAxis(PadAxis::LeftStickY, AxisDir::Negative), Binding::PadButton(PadButton::DPadDown),
+        ]);
+        actions.insert(Action::MoveLeft, vec![
+            Binding::Key(VirtualKeyCode::A), Binding::Key(VirtualKeyCode::Left),
+            Binding::PadAxis(PadAxis::LeftStickX, AxisDir::Negative), Binding::PadButton(PadButton::DPadLeft),
+        ]);
+        actions.insert(Action::MoveRight, vec![
+            Binding::Key(VirtualKeyCode::D), Binding::Key(VirtualKeyCode::Right),
+            Binding::PadAxis(PadAxis::LeftStickX, AxisDir::Positive), Binding::PadButton(PadButton::DPadRight),
+        ]);
+        actions.insert(Action::Fire, vec![
+            Binding::Mouse(Mouse::Left), Binding::PadButton(PadButton::RightTrigger),
+        ]);
+        Bindings { actions }
+    }
+}
+
+impl Bindings {
+    // a missing or broken file gives the defaults
+    pub fn load<P: AsRef<Path>>(path: P) -> Bindings
+    {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Bindings::default(),
+        };
+        match toml::from_str(&text) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                warn!("could not parse {}: {}", path.display(), e);
+                Bindings::default()
+            }
+        }
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()>
+    {
+        let text = toml::to_string_pretty(self).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, text)
+    }
+    // the strongest of all bound inputs, so a half pushed stick stays half
+    pub fn value(&self, action: Action, ui: &Ui, pad: &PadState) -> f32
+    {
+        self.actions.get(&action).map_or(0.0f32, |bindings| {
+            bindings.iter().map(|b| b.value(ui, pad)).fold(0.0f32, f32::max)
+        })
+    }
+}
+
+
+// state of the first connected gamepad, written by the gamepad thread
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PadState
+{
+    pub axes: [f32; ALL_AXES.len()],
+    pub buttons: [f32; ALL_BUTTONS.len()],
+}
+
+impl PadState {
+    // the first input that is pushed far enough, for rebinding
+    fn pressed_binding(&self) -> Option<Binding>
+    {
+        if let Some(b) = ALL_BUTTONS.iter().find(|b| self.buttons[**b as usize] > CAPTURE_THRESHOLD) {
+            return Some(Binding::PadButton(*b));
+        }
+        ALL_AXES.iter().find(|a| self.axes[**a as usize].abs() > CAPTURE_THRESHOLD).map(|a| {
+            let dir = if self.axes[*a as usize] > 0.0f32 { AxisDir::Positive } else { AxisDir::Negative };
+            Binding::PadAxis(*a, dir)
+        })
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn spawn_gamepad_thread(state: Arc<Mutex<PadState>>)
+{
+    use gilrs::{Axis, Button, Gilrs};
+    std::thread::spawn(move || {
+        // gilrs is polled on its own thread as it is not Send on every platform
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                warn!("gamepads unavailable: {}", e);
+                return;
+            }
+        };
+        loop {
+            while gilrs.next_event().is_some() {}
+            let mut pad = PadState::default();
+            if let Some((_, gamepad)) = gilrs.gamepads().find(|(_, g)| g.is_connected()) {
+                for axis in ALL_AXES {
+                    pad.axes[axis as usize] = gamepad.value(match axis {
+                        PadAxis::LeftStickX => Axis::LeftStickX,
+                        PadAxis::LeftStickY => Axis::LeftStickY,
+                        PadAxis::RightStickX => Axis::RightStickX,
+                        PadAxis::RightStickY => Axis::RightStickY,
+                    });
+                }
+                for button in ALL_BUTTONS {
+                    let b = match button {
+                        PadButton::South => Button::South,
+                        PadButton::East => Button::East,
+                        PadButton::North => Button::North,
+                        PadButton::West => Button::West,
+                        PadButton::LeftBumper => Button::LeftTrigger,
+                        PadButton::RightBumper => Button::RightTrigger,
+                        PadButton::LeftTrigger => Button::LeftTrigger2,
+                        PadButton::RightTrigger => Button::RightTrigger2,
+                        PadButton::DPadUp => Button::DPadUp,
+                        PadButton::DPadDown => Button::DPadDown,
+                        PadButton::DPadLeft => Button::DPadLeft,
+                        PadButton::DPadRight => Button::DPadRight,
+                        PadButton::Select => Button::Select,
+                        PadButton::Start => Button::Start,
+                    };
+                    pad.buttons[button as usize] = gamepad.button_data(b).map_or(0.0f32, |d| d.value());
+                }
+            }
+            *state.lock().unwrap() = pad;
+            std::thread::sleep(std::time::Duration::from_millis(4));
+        }
+    });
+}
+
+#[cfg(not(feature = "gamepad"))]
+fn spawn_gamepad_thread(_state: Arc<Mutex<PadState>>)
+{
+}
+
+
+// movement sampled from the bindings, up_down is negative upwards like the game area
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputValues
+{
+    pub left_right: f32,
+    pub up_down: f32,
+    pub fire: bool,
+}
+
+pub struct InputMap
+{
+    pub bindings: Bindings,
+    path: String,
+    pad: Arc<Mutex<PadState>>,
+    // the action the next pressed input gets bound to
+    capturing: Option<Action>,
+}
+
+impl InputMap {
+    pub fn new(path: &str) -> InputMap
+    {
+        let pad = Arc::new(Mutex::new(PadState::default()));
+        spawn_gamepad_thread(pad.clone());
+        InputMap {
+            bindings: Bindings::load(path),
+            path: path.to_string(),
+            pad,
+            capturing: None,
+        }
+    }
+
+    pub fn sample(&self, ui: &Ui) -> InputValues
+    {
+        let pad = *self.pad.lock().unwrap();
+        if self.capturing.is_some() {
+            return InputValues::default();
+        }
+        let value = |action| self.bindings.value(action, ui, &pad);
+        InputValues {
+            left_right: value(Action::MoveRight) - value(Action::MoveLeft),
+            up_down: value(Action::MoveDown) - value(Action::MoveUp),
+            fire: value(Action::Fire) > 0.5f32,
+        }
+    }
+
+    fn save(&self)
+    {
+        if let Err(e) = self.bindings.save(&self.path) {
+            warn!("could not save {}: {}", self.path, e);
+        }
+    }
+
+    // lists the bindings of every action, "+" waits for the next input to bind, escape cancels
+    pub fn draw_ui(&mut self, ui: &Ui)
+    {
+        if let Some(action) = self.capturing {
+            let io = ui.io();
+            let pad = *self.pad.lock().unwrap();
+            let captured = if io.keys_down[VirtualKeyCode::Escape as usize] {
+                self.capturing = None;
+                None
+            }
+            else if let Some(key) = BINDABLE_KEYS.iter().find(|k| io.keys_down[**k as usize]) {
+                Some(Binding::Key(*key))
+            }
+            else if ui.is_mouse_clicked(MouseButton::Left) {
+                Some(Binding::Mouse(Mouse::Left))
+            }
+            else if ui.is_mouse_clicked(MouseButton::Right) {
+                Some(Binding::Mouse(Mouse::Right))
+            }
+            else if ui.is_mouse_clicked(MouseButton::Middle) {
+                Some(Binding::Mouse(Mouse::Middle))
+            }
+            else {
+                pad.pressed_binding()
+            };
+            if let Some(binding) = captured {
+                let list = self.bindings.actions.entry(action).or_default();
+                if !list.contains(&binding) {
+                    list.push(binding);
+                }
+                self.capturing = None;
+                self.save();
+            }
+        }
+
+        let mut changed = false;
+        Window::new("Controls")
+           .size([320.0, 220.0], Condition::FirstUseEver)
+           .build(ui, || {
+               for action in ALL_ACTIONS {
+                   let _id = ui.push_id(action as i32);
+                   ui.text(format!("{:?}:", action));
+                   let mut remove = None;
+                   for (i, binding) in self.bindings.actions.get(&action).into_iter().flatten().enumerate() {
+                       ui.same_line();
+                       let _id = ui.push_id(i as i32);
+                       if ui.small_button(binding.name()) {
+                           remove = Some(i);
+                       }
+                       if ui.is_item_hovered() {
+                           ui.tooltip_text("click to remove");
+                       }
+                   }
+                   if let Some(i) = remove {
+                       self.bindings.actions.get_mut(&action).unwrap().remove(i);
+                       changed = true;
+                   }
+                   ui.same_line();
+                   if self.capturing == Some(action) {
+                       ui.text("press an input...");
+                   }
+                   else if ui.small_button("+") {
+                       self.capturing = Some(action);
+                   }
+               }
+               ui.separator();
+               if ui.button("Reset to defaults") {
+                   self.bindings = Bindings::default();
+                   self.capturing = None;
+                   changed = true;
+               }
+           });
+        if changed {
+            self.save();
+        }
+    }
+}
@@ -1,201 +1,389 @@
-#[macro_use]
-extern crate glium;
-
-use glium::glutin::event::{ KeyboardInput, ElementState, VirtualKeyCode};
-#[allow(unused_imports)]
-use glium::{glutin, Surface};
+use std::collections::VecDeque;
 use std::thread;
-use std::sync::{mpsc, mpsc::Receiver};
-use std::time::{SystemTime};
+use std::sync::{Arc, Mutex, mpsc, mpsc::Receiver};
 
 use imgui::*;
 
 extern crate common;
 use common::*;
+use tracing::{debug, error, info, info_span, trace, warn};
+
+use std::net::{SocketAddr, TcpStream};
+
+mod input;
+use input::*;
+mod config;
+use config::*;
 
-use std::net::{TcpStream};
-use std::io::{prelude::*};
+// announcements shown in the window
+const MAX_SERVER_MESSAGES: usize = 5;
 
 
+#[derive(Default)]
+struct SampledInput
+{
+    left_right: f32,
+    up_down: f32,
+    fire: bool,
+    // world position the mouse points at
+    aim: [f32; 2],
+}
+
 struct ClientData
 {
-    stream: TcpStream,
+    writer: MessageWriter<TcpStream>,
     receiver: Receiver<NetworkMessages>,
-    all_players: Vec<Player>,
+    world: World,
+    replication: Replication,
     last_input: PlayerInput,
-    last_time: SystemTime,
-    local_player_id: u32,
-    timer: f32,
+    // resent with every input message in case earlier ones got lost
+    recent_inputs: VecDeque<PlayerInput>,
+    // sampled every frame, consumed by the next tick
+    sampled_input: SampledInput,
+    input: InputMap,
+    // server tick of the latest world state we applied, sent along with shots
+    view_tick: u64,
+    fire_timer: f32,
+    clock: ClockSync,
+    camera: Camera,
+    local_player_id: Entity,
     predict_movement: bool,
+    server_addr: SocketAddr,
+    encrypted: bool,
+    compressed: bool,
+    // from the WorldState the client joined with
+    settings: ServerSettings,
+    stats: Arc<Mutex<NetStats>>,
+    tick_timing: TickTiming,
+    // distance between the predicted and the received position of the local player
+    prediction_error: Plot,
+    // set by the server admin
+    frozen: bool,
+    kicked: Option<String>,
+    // a write failed, nothing is sent after that
+    disconnected: bool,
+    // the latest announcements of the server
+    server_messages: VecDeque<String>,
 }
 
 
+impl ClientData {
+    // estimated current time on the server clock
+    fn server_time(&self) -> f64
+    {
+        self.clock.server_time()
+    }
+    fn send(&mut self, msg: &NetworkMessages)
+    {
+        // the server closes the connection right after Kicked
+        if self.kicked.is_some() || self.disconnected {
+            return;
+        }
+        match self.writer.send(msg) {
+            Ok(size) => self.stats.lock().unwrap().record_sent(msg, size),
+            Err(e) => {
+                warn!("could not send {}, the connection is lost: {}", msg.name(), e);
+                self.disconnected = true;
+            }
+        }
+    }
+    fn draw_diagnostics(&mut self, ui: &Ui)
+    {
+        let mut stats = self.stats.lock().unwrap();
+        stats.update();
+        Window::new("Diagnostics")
+           .size([360.0, 420.0], Condition::FirstUseEver)
+           .position([20.0, 140.0], Condition::FirstUseEver)
+           .build(ui, || {
+               stats.draw(ui);
+               if CollapsingHeader::new("Connections").default_open(true).build(ui) {
+                   let rtt = if self.clock.is_synced() { format!("{:.2}ms", self.clock.rtt() * 1000.0) } else { "-".to_string() };
+                   ui.text(format!("Server {} RTT {}", self.server_addr, rtt));
+                   ui.text(format!(
+                       "{}, {}",
+                       if self.encrypted { "encrypted" } else { "not encrypted" },
+                       if self.compressed { "compressed" } else { "not compressed" }
+                   ));
+               }
+               if CollapsingHeader::new("Ticks").default_open(true).build(ui) {
+                   self.tick_timing.draw(ui);
+               }
+               if CollapsingHeader::new("Prediction").default_open(true).build(ui) {
+                   self.prediction_error.draw(ui, "##prediction_error", &format!("error {:.2} (max {:.2})", self.prediction_error.last(), self.prediction_error.max()));
+               }
+           });
+    }
+}
 
 
 
-impl Updater for ClientData {
-    fn update(&mut self, ui: &Ui, screen_sz: &[f32; 2])
+impl Game for ClientData {
+    // the same as the server so predicted and simulated movement match
+    fn tick_rate(&self) -> f32
     {
-        let cur = SystemTime::now();
-        let dt = cur.duration_since(self.last_time).unwrap();
-        let in_ms = dt.as_secs_f32();
-        self.timer += in_ms;
-        self.last_time = cur;
-
-        for msg in &self.receiver.try_recv() {
+        self.clock.tick_dt().unwrap_or(self.settings.tick_dt)
+    }
+    fn fixed_update(&mut self, _tick: u64, dt: f32)
+    {
+        let tick_start = self.tick_timing.begin();
+        while let Ok(msg) = self.receiver.try_recv() {
             match msg {
-                NetworkMessages::ClientInputChange(input) => {
-                    for p in &mut self.all_players
-                    {
-                        if p.id == input.id {
-                            break;
-                        }
+                NetworkMessages::WorldState{local_id, tick, updates, map, settings} => {
+                    self.view_tick = tick;
+                    for update in &updates {
+                        self.replication.apply(&mut self.world, update);
+                    }
+                    self.local_player_id = local_id;
+                    self.settings = settings;
+                    if !is_valid_tick_dt(settings.tick_dt) {
+                        warn!("ignoring the invalid tick length {} of the server", settings.tick_dt);
+                        self.settings.tick_dt = TICK_RATE;
+                    }
+                    // movement is clamped to the map on both sides, prediction goes wrong when they differ
+                    if map != MapInfo::default() {
+                        warn!("the server map is {}x{}, this client was built for {}x{}", map.width, map.height, GAME_AREA_WIDTH, GAME_AREA_HEIGHT);
                     }
-                }
-                NetworkMessages::AddLocal(player) => {
-                    self.all_players.push(Player{ id: player.id, cur_sequence_id: player.cur_sequence_id, pos: player.pos, col: player.col });
-                    self.local_player_id = player.id;
                 }
                 NetworkMessages::AddPlayer(player) => {
-                    self.all_players.push(Player{ id: player.id, cur_sequence_id: player.cur_sequence_id, pos: player.pos, col: player.col });
+                    if self.world.contains(player.id) || self.world.spawn_at(player.id) {
+                        self.world.insert(player.id, player);
+                    }
+                    else {
+                        debug!(player = player.id, "ignoring AddPlayer for a stale id");
+                    }
+                }
+                NetworkMessages::RemovePlayer{id} | NetworkMessages::DespawnEntity{id} => {
+                    let despawned = self.world.despawn(id);
+                    if !despawned && self.world.is_stale(id) {
+                        debug!(entity = id, "ignoring the despawn of a stale entity");
+                    }
                 }
-                NetworkMessages::RemovePlayer{id} => {
-                    for i in 0..self.all_players.len()
-                    {
-                        if self.all_players.get(i).unwrap().id == *id {
-                            self.all_players.remove(i);
-                            break;
-                        }
+                NetworkMessages::WorldUpdate{tick, updates} => {
+                    let predicted = self.world.get::<Player>(self.local_player_id).map(|p| p.pos);
+                    for update in &updates {
+                        self.replication.apply(&mut self.world, update);
+                    }
+                    if let (Some(predicted), Some(p)) = (predicted, self.world.get::<Player>(self.local_player_id)) {
+                        let (dx, dy) = (p.pos[0] - predicted[0], p.pos[1] - predicted[1]);
+                        self.prediction_error.push((dx * dx + dy * dy).sqrt());
                     }
+                    self.view_tick = self.view_tick.max(tick);
                 }
-                NetworkMessages::Position(player) => {
-                    for p in &mut self.all_players
-                    {
-                        if p.id == player.id {
-                            p.pos = player.pos;
-                            break;
-                        }
+                NetworkMessages::ClockResponse{client_time, server_receive_time, server_send_time, server_tick, tick_dt} => {
+                    self.clock.handle_response(client_time, server_receive_time, server_send_time, server_tick, tick_dt);
+                }
+                NetworkMessages::Kicked{reason} => {
+                    warn!("kicked by the server: {}", reason);
+                    self.kicked = Some(reason);
+                }
+                NetworkMessages::SetFrozen{frozen} => {
+                    self.frozen = frozen;
+                }
+                NetworkMessages::ServerMessage{text} => {
+                    info!("server: {}", text);
+                    if self.server_messages.len() == MAX_SERVER_MESSAGES {
+                        self.server_messages.pop_front();
                     }
+                    self.server_messages.push_back(text);
+                }
+                NetworkMessages::Ping{server_time} => {
+                    self.send(&NetworkMessages::Pong{ server_time });
                 }
                 _ => {
                 }
             };
         }
 
-        while self.timer > TICK_RATE {
-            let mut left_right = 0.0f32;
-            let mut up_down = 0.0f32;
-            if ui.is_key_down(Key::UpArrow) { up_down -= 1.0f32;}
-            if ui.is_key_down(Key::DownArrow) { up_down += 1.0f32;}
-            if ui.is_key_down(Key::LeftArrow) { left_right -= 1.0f32;}
-            if ui.is_key_down(Key::RightArrow) { left_right += 1.0f32;}
-            if left_right != self.last_input.left_right || up_down != self.last_input.up_down {
-                for p in &mut self.all_players {
-                    if p.id == self.local_player_id {
-
-                        p.cur_sequence_id += 1;
-                        let p_inputs = PlayerInput{
-                            id: p.id,
-                            cur_sequence_id: p.cur_sequence_id,
-                            left_right: left_right,
-                            up_down: up_down,
-                        };
-                        self.last_input = p_inputs.clone();
-
-                        let msg: NetworkMessages = NetworkMessages::ClientInputChange(p_inputs);
-                        let serialized_msg = bincode::serialize(&msg).unwrap();
-                        self.stream.write(&serialized_msg.as_slice()).unwrap();
-
-                        break;
-                    }
-                }
+        if let Some(request) = self.clock.poll_request() {
+            self.send(&request);
+        }
+
+        let SampledInput { left_right, up_down, fire, aim } = self.sampled_input;
+        if self.world.contains(self.local_player_id) {
+            let p_inputs = PlayerInput{
+                id: self.local_player_id,
+                cur_sequence_id: self.last_input.cur_sequence_id + 1,
+                left_right,
+                up_down,
+            };
+            self.last_input = p_inputs;
+            if self.recent_inputs.len() == INPUT_REDUNDANCY {
+                self.recent_inputs.pop_front();
             }
+            self.recent_inputs.push_back(p_inputs);
 
-            if self.predict_movement {
-                for p in &mut self.all_players {
-                    if p.id == self.local_player_id {
-                        p.update(&self.last_input, TICK_RATE);
-                    }
-                }
+            let msg = NetworkMessages::ClientInput{ inputs: self.recent_inputs.iter().copied().collect() };
+            self.send(&msg);
+        }
+
+        self.fire_timer -= dt;
+        if fire && !self.frozen && self.fire_timer <= 0.0f32 {
+            if let Some(p) = self.world.get::<Player>(self.local_player_id) {
+                let center = p.center();
+                let fire = FireInput{
+                    id: self.local_player_id,
+                    cur_sequence_id: self.last_input.cur_sequence_id,
+                    dir: [aim[0] - center[0], aim[1] - center[1]],
+                    view_tick: self.view_tick as f64,
+                };
+                self.send(&NetworkMessages::ClientFire(fire));
+                self.fire_timer = self.settings.fire_cooldown;
             }
+        }
 
-            self.timer -= TICK_RATE;
+        if self.predict_movement && !self.frozen {
+            if let Some(p) = self.world.get_mut::<Player>(self.local_player_id) {
+                p.update(&self.last_input, dt);
+            }
         }
+        self.tick_timing.end(tick_start);
+    }
+
+    fn update(&mut self, ui: &Ui, dt: f32)
+    {
+        self.camera.handle_ui(ui);
+
+        let values = self.input.sample(ui);
+        self.sampled_input = SampledInput{
+            left_right: values.left_right,
+            up_down: values.up_down,
+            fire: values.fire,
+            aim: self.camera.screen_to_world(&ui.io().mouse_pos),
+        };
 
+        if let Some(p) = self.world.get::<Player>(self.local_player_id) {
+            let target = p.center();
+            self.camera.follow(&target, dt);
+        }
+    }
+
+    fn render(&mut self, ui: &Ui, renderer: &mut MyRenderer)
+    {
+        let mut follow = self.camera.mode == CameraMode::Follow;
         Window::new("Test Window")
            .size([300.0, 100.0], Condition::FirstUseEver)
-           .build(&ui, || {
+           .build(ui, || {
                ui.text("Test!");
+               if let Some(reason) = &self.kicked {
+                   ui.text_colored([1.0, 0.3, 0.3, 1.0], format!("Kicked: {}", reason));
+               }
+               else if self.disconnected {
+                   ui.text_colored([1.0, 0.3, 0.3, 1.0], "Connection lost");
+               }
+               if self.frozen {
+                   ui.text_colored([0.5, 0.7, 1.0, 1.0], "Frozen by the server");
+               }
+               for text in &self.server_messages {
+                   ui.text_wrapped(format!("[SERVER] {}", text));
+               }
+               ui.checkbox("Follow local player", &mut follow);
+               if let Some(p) = self.world.get::<Player>(self.local_player_id) {
+                   ui.text(format!("Score: {}", p.score));
+               }
+               if self.clock.is_synced() {
+                   ui.text(format!("Server time: {:.3}s (tick {:.1})", self.server_time(), self.clock.server_tick()));
+                   ui.text(format!("Offset: {:.2}ms RTT: {:.2}ms Drift: {:.1}ppm", self.clock.offset() * 1000.0, self.clock.rtt() * 1000.0, self.clock.drift() * 1e6));
+               }
                ui.separator();
                let mouse_pos = ui.io().mouse_pos;
+               let mouse_world = self.camera.screen_to_world(&mouse_pos);
                ui.text(format!(
-                   "Mouse Position: ({:.1},{:.1})",
-                   mouse_pos[0], mouse_pos[1]
+                   "Mouse Position: ({:.1},{:.1}) World: ({:.1},{:.1})",
+                   mouse_pos[0], mouse_pos[1], mouse_world[0], mouse_world[1]
                ));
            });
-  
-        let player_size = world_to_screen(&screen_sz, &[PLAYER_SIZE, PLAYER_SIZE]);
-        for p in &self.all_players {
-            let draw_list = ui.get_background_draw_list();
-            let pw = world_to_screen(&screen_sz, &p.pos);
-            draw_list.add_rect(pw, [pw[0] + player_size[0], pw[1] + player_size[1]], p.col).filled(true).build();
-        }
+        self.camera.mode = if follow { CameraMode::Follow } else { CameraMode::Free };
+        self.input.draw_ui(ui);
+        self.draw_diagnostics(ui);
+
+        renderer.set_view_matrix(self.camera.view_matrix());
+        renderer.draw_world(&self.world);
     }
-    
+
 }
 
 
 fn main() {
     let (sender, receiver) = mpsc::channel::<NetworkMessages>();
-    let mut read_stream = TcpStream::connect("127.0.0.1:7878").unwrap();
-    let mut client_data = ClientData{
-        stream: read_stream.try_clone().unwrap(),
-        receiver: receiver,
-        last_input: { PlayerInput { id: u32::MAX, cur_sequence_id: 0, up_down: 0.0f32, left_right: 0.0f32 }},
-        last_time: SystemTime::now(),
-        timer: 0.0f32,
-        local_player_id: u32::MAX,
-        all_players: Vec::new(),
+    let config = ClientConfig::load(CLIENT_CONFIG_FILE);
+    if let Err(e) = init_logging(config.as_ref().ok().and_then(|c| c.log_file())) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let config = config.unwrap_or_else(|e| {
+        warn!("{}", e);
+        ClientConfig::default()
+    });
+    // the client has a single connection, so everything it logs is part of it
+    let span = info_span!("connection", addr = %config.address, user = %config.user);
+    let _enter = span.enter();
+    let mut read_stream = TcpStream::connect(&config.address).unwrap_or_else(|e| {
+        error!("could not connect: {}", e);
+        std::process::exit(1);
+    });
+    let Connection { mut reader, writer, server_key, compressed } = connect_to_server(&read_stream, &config.connect_options()).unwrap_or_else(|e| {
+        error!("could not connect: {}", e);
+        std::process::exit(1);
+    });
+    info!(encrypted = server_key.is_some(), compressed, "connected");
+    if let Some(key) = server_key.as_ref().filter(|_| config.server_key.is_empty()) {
+        info!("the server key is {}, set it as server_key in {} to only trust this server", to_hex(key), CLIENT_CONFIG_FILE);
+    }
+    let stats = Arc::new(Mutex::new(NetStats::new()));
+    let mut camera = Camera::new([1024.0f32, 768.0f32]);
+    camera.mode = CameraMode::Follow;
+    camera.zoom = 2.0f32;
+    let client_data = ClientData{
+        writer,
+        receiver,
+        last_input: PlayerInput { id: INVALID_ENTITY, ..Default::default() },
+        recent_inputs: VecDeque::new(),
+        sampled_input: SampledInput::default(),
+        input: InputMap::new(BINDINGS_FILE),
+        view_tick: 0,
+        fire_timer: 0.0f32,
+        clock: ClockSync::new(),
+        camera,
+        local_player_id: INVALID_ENTITY,
+        world: World::new(),
+        replication: create_replication(),
         predict_movement: true,
+        server_addr: read_stream.peer_addr().unwrap(),
+        encrypted: server_key.is_some(),
+        compressed,
+        stats: stats.clone(),
+        tick_timing: TickTiming::default(),
+        prediction_error: Plot::default(),
+        frozen: false,
+        kicked: None,
+        disconnected: false,
+        server_messages: VecDeque::new(),
+        settings: ServerSettings::default(),
     };
 
+    let reader_span = span.clone();
     thread::spawn(move || {
+        let _enter = reader_span.enter();
         loop {
-                let mut data = [0 as u8; 500];
-                match read_stream.read(&mut data) {
-                Ok(size) => {
-                    if size > 0 {
-                        let mut cursor = &data[..];
-                        while cursor.len() > 0 {
-                            let msg: Result<NetworkMessages, _> = bincode::deserialize_from(&mut cursor);
-                            if msg.is_ok() {
-                                let real_msg = msg.unwrap();
-                                if !matches!(real_msg, NetworkMessages::InvalidMessage) {
-                                    sender.send(real_msg).unwrap();
-                                }
-                            }
-                        }
-                    }
-                    else {
-                        println!("An error occurred, terminating connection with {}", read_stream.peer_addr().unwrap());
-                        break;
+            match reader.read_from(&mut read_stream) {
+                Ok(messages) => {
+                    for (msg, size) in messages {
+                        trace!(message_type = msg.name(), size, "received");
+                        stats.lock().unwrap().record_received(&msg, size);
+                        sender.send(msg).unwrap();
                     }
                 },
-                _ => {
-                    println!("An error occurred, terminating connection with {}", read_stream.peer_addr().unwrap());
+                Err(e) => {
+                    info!("connection closed: {}", e);
                     break;
                 }
-            } { 
-    
-             }
+            }
         }
     });
 
 
 
     let r = MyRenderer::new("Client");
-    r.run(move |run, ui, sz| {
-        client_data.update(&ui, sz);
-    });
+    r.run_game(client_data, LoopMode::FixedUpdateThread);
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+
+pub const ATLAS_SIZE: u32 = 1024;
+// the top left corner is kept white so untextured quads just show their colour
+const WHITE_SIZE: u32 = 2;
+// the middle of the white block, stays white with linear filtering
+pub const WHITE_UV: [f32; 2] = [1.0f32 / ATLAS_SIZE as f32, 1.0f32 / ATLAS_SIZE as f32];
+const SPRITE_PADDING: u32 = 1;
+pub const PLAYER_SPRITE_SIZE: u32 = 32;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteId(pub u32);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub uv_start: [f32; 2],
+    pub uv_size: [f32; 2],
+    pub size: [u32; 2],
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    Image(image::ImageError),
+    Full,
+    // the pixel data does not hold width * height rgba pixels
+    SizeMismatch{ expected: usize, got: usize },
+}
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::Image(e) => write!(f, "failed to load image: {}", e),
+            AtlasError::Full => write!(f, "the sprite atlas is full"),
+            AtlasError::SizeMismatch{ expected, got } => write!(f, "expected {} bytes of pixels, got {}", expected, got),
+        }
+    }
+}
+impl std::error::Error for AtlasError {}
+
+
+// rgba8 image all sprites are packed into, shelf by shelf from the top.
+// The pixels stay on the cpu, the renderer uploads them again whenever they changed
+pub struct SpriteAtlas {
+    pixels: Vec<u8>,
+    sprites: Vec<Sprite>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    dirty: bool,
+}
+
+impl Default for SpriteAtlas {
+    fn default() -> Self {
+        SpriteAtlas::new()
+    }
+}
+
+impl SpriteAtlas {
+    pub fn new() -> SpriteAtlas
+    {
+        let mut atlas = SpriteAtlas {
+            pixels: vec![0u8; (ATLAS_SIZE * ATLAS_SIZE * 4) as usize],
+            sprites: Vec::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            dirty: true,
+        };
+        atlas.add_rgba(WHITE_SIZE, WHITE_SIZE, &vec![255u8; (WHITE_SIZE * WHITE_SIZE * 4) as usize]).unwrap();
+        atlas
+    }
+
+    pub fn size(&self) -> [u32; 2]
+    {
+        [ATLAS_SIZE, ATLAS_SIZE]
+    }
+    pub fn pixels(&self) -> &[u8]
+    {
+        &self.pixels
+    }
+    pub fn get(&self, id: SpriteId) -> Option<&Sprite>
+    {
+        self.sprites.get(id.0 as usize)
+    }
+    // returns true once after every change
+    pub fn take_dirty(&mut self) -> bool
+    {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<SpriteId, AtlasError>
+    {
+        let img = image::open(path).map_err(AtlasError::Image)?.to_rgba8();
+        let (w, h) = img.dimensions();
+        self.add_rgba(w, h, img.as_raw())
+    }
+
+    pub fn add_rgba(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<SpriteId, AtlasError>
+    {
+        // checked before any size math so huge sizes can not overflow
+        if width > ATLAS_SIZE - SPRITE_PADDING || height > ATLAS_SIZE - SPRITE_PADDING {
+            return Err(AtlasError::Full);
+        }
+        let expected = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(4));
+        if expected != Some(rgba.len()) {
+            return Err(AtlasError::SizeMismatch{ expected: expected.unwrap_or(usize::MAX), got: rgba.len() });
+        }
+        if self.shelf_x + width + SPRITE_PADDING > ATLAS_SIZE {
+            self.shelf_y += self.shelf_height;
+            self.shelf_x = 0;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y + height + SPRITE_PADDING > ATLAS_SIZE {
+            return Err(AtlasError::Full);
+        }
+        let (x, y) = (self.shelf_x, self.shelf_y);
+        for row in 0..height {
+            let src = (row * width * 4) as usize;
+            let dst = (((y + row) * ATLAS_SIZE + x) * 4) as usize;
+            self.pixels[dst..dst + (width * 4) as usize].copy_from_slice(&rgba[src..src + (width * 4) as usize]);
+        }
+        self.shelf_x += width + SPRITE_PADDING;
+        self.shelf_height = self.shelf_height.max(height + SPRITE_PADDING);
+        self.dirty = true;
+
+        let inv = 1.0f32 / ATLAS_SIZE as f32;
+        self.sprites.push(Sprite {
+            uv_start: [x as f32 * inv, y as f32 * inv],
+            uv_size: [width as f32 * inv, height as f32 * inv],
+            size: [width, height],
+        });
+        Ok(SpriteId(self.sprites.len() as u32 - 1))
+    }
+}
+
+
+pub const PLAYER_SPRITE_COUNT: u32 = 6;
+
+// white player shapes with soft edges, they get tinted by the player colour.
+// 0 square, 1 circle, 2 diamond, 3 ring, 4 triangle, 5 cross
+pub fn generate_player_sprite(index: u32) -> Vec<u8>
+{
+    let size = PLAYER_SPRITE_SIZE;
+    let mut pixels = vec![0u8; (size * size * 4) as usize];
+    let half = size as f32 * 0.5f32;
+    for y in 0..size {
+        for x in 0..size {
+            // -1..1 over the sprite, sampled at the pixel center
+            let px = (x as f32 + 0.5f32 - half) / half;
+            let py = (y as f32 + 0.5f32 - half) / half;
+            let r = (px * px + py * py).sqrt();
+            // signed distance like value, inside when negative
+            let d = match index % PLAYER_SPRITE_COUNT {
+                0 => px.abs().max(py.abs()) - 0.95f32,
+                1 => r - 0.95f32,
+                2 => px.abs() + py.abs() - 0.98f32,
+                3 => (r - 0.72f32).abs() - 0.24f32,
+                4 => f32::max(py - 0.85f32, px.abs() * 2.0f32 - (py + 0.85f32) * 0.95f32) * 0.6f32,
+                _ => f32::min(px.abs() - 0.3f32, py.abs() - 0.3f32).max(px.abs().max(py.abs()) - 0.95f32),
+            };
+            let coverage = (0.5f32 - d * half).clamp(0.0f32, 1.0f32);
+            let i = ((y * size + x) * 4) as usize;
+            pixels[i..i + 4].copy_from_slice(&[255, 255, 255, (coverage * 255.0f32) as u8]);
+        }
+    }
+    pixels
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_sprites_that_do_not_fit()
+    {
+        let mut atlas = SpriteAtlas::new();
+        assert!(matches!(atlas.add_rgba(u32::MAX, u32::MAX, &[]), Err(AtlasError::Full)));
+        assert!(matches!(atlas.add_rgba(ATLAS_SIZE, 1, &vec![0u8; ATLAS_SIZE as usize * 4]), Err(AtlasError::Full)));
+        assert!(matches!(atlas.add_rgba(4, 4, &[0u8; 4 * 4 * 4 - 1]), Err(AtlasError::SizeMismatch{ expected: 64, got: 63 })));
+        let id = atlas.add_rgba(4, 4, &[0u8; 4 * 4 * 4]).unwrap();
+        assert_eq!(atlas.get(id).unwrap().size, [4, 4]);
+    }
+}
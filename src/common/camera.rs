@@ -0,0 +1,134 @@
+use imgui::{MouseButton, Ui};
+use crate::game::{GAME_AREA_WIDTH, GAME_AREA_HEIGHT};
+use crate::quad::ortho_matrix;
+
+
+pub const MIN_ZOOM: f32 = 0.25f32;
+pub const MAX_ZOOM: f32 = 8.0f32;
+// how quickly the camera catches up with the followed target, per second
+const FOLLOW_SPEED: f32 = 8.0f32;
+const WHEEL_ZOOM_STEP: f32 = 1.1f32;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    // panned and zoomed with the mouse
+    Free,
+    // centered on whatever follow() is called with
+    Follow,
+}
+
+
+// 2d camera looking at `position` (the world point in the middle of the screen).
+// At zoom 1 the whole game area height is visible, the width follows the aspect ratio of the viewport
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub position: [f32; 2],
+    pub zoom: f32,
+    pub mode: CameraMode,
+    viewport: [f32; 2],
+}
+
+impl Camera {
+    pub fn new(viewport: [f32; 2]) -> Camera
+    {
+        Camera {
+            position: [GAME_AREA_WIDTH * 0.5f32, GAME_AREA_HEIGHT * 0.5f32],
+            zoom: 1.0f32,
+            mode: CameraMode::Free,
+            viewport,
+        }
+    }
+
+    pub fn set_viewport(&mut self, viewport: [f32; 2])
+    {
+        self.viewport = [viewport[0].max(1.0f32), viewport[1].max(1.0f32)];
+    }
+    pub fn viewport(&self) -> [f32; 2]
+    {
+        self.viewport
+    }
+    // shows the whole game area
+    pub fn fit_world(&mut self)
+    {
+        self.position = [GAME_AREA_WIDTH * 0.5f32, GAME_AREA_HEIGHT * 0.5f32];
+        let aspect = self.viewport[0] / self.viewport[1];
+        self.zoom = f32::min(1.0f32, aspect * GAME_AREA_HEIGHT / GAME_AREA_WIDTH);
+    }
+
+    // world units visible on screen
+    pub fn view_size(&self) -> [f32; 2]
+    {
+        let h = GAME_AREA_HEIGHT / self.zoom;
+        [h * self.viewport[0] / self.viewport[1], h]
+    }
+    pub fn pixels_per_unit(&self) -> f32
+    {
+        self.viewport[1] / self.view_size()[1]
+    }
+
+    pub fn world_to_screen(&self, pos: &[f32; 2]) -> [f32; 2]
+    {
+        let scale = self.pixels_per_unit();
+        [
+            (pos[0] - self.position[0]) * scale + self.viewport[0] * 0.5f32,
+            (pos[1] - self.position[1]) * scale + self.viewport[1] * 0.5f32,
+        ]
+    }
+    pub fn screen_to_world(&self, pos: &[f32; 2]) -> [f32; 2]
+    {
+        let scale = self.pixels_per_unit();
+        [
+            (pos[0] - self.viewport[0] * 0.5f32) / scale + self.position[0],
+            (pos[1] - self.viewport[1] * 0.5f32) / scale + self.position[1],
+        ]
+    }
+    pub fn view_matrix(&self) -> [[f32; 4]; 4]
+    {
+        let [w, h] = self.view_size();
+        ortho_matrix(self.position[0] - w * 0.5f32, self.position[0] + w * 0.5f32, self.position[1] - h * 0.5f32, self.position[1] + h * 0.5f32)
+    }
+
+    // moves the camera by a distance in screen pixels
+    pub fn pan(&mut self, screen_delta: &[f32; 2])
+    {
+        let scale = self.pixels_per_unit();
+        self.position[0] -= screen_delta[0] / scale;
+        self.position[1] -= screen_delta[1] / scale;
+    }
+    // zooms while keeping the world point under screen_pos where it is
+    pub fn zoom_at(&mut self, screen_pos: &[f32; 2], factor: f32)
+    {
+        let before = self.screen_to_world(screen_pos);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let after = self.screen_to_world(screen_pos);
+        self.position[0] += before[0] - after[0];
+        self.position[1] += before[1] - after[1];
+    }
+    // smoothly moves towards the target, only does something in follow mode
+    pub fn follow(&mut self, target: &[f32; 2], dt: f32)
+    {
+        if self.mode != CameraMode::Follow {
+            return;
+        }
+        let t = 1.0f32 - (-FOLLOW_SPEED * dt).exp();
+        self.position[0] += (target[0] - self.position[0]) * t;
+        self.position[1] += (target[1] - self.position[1]) * t;
+    }
+
+    // mouse wheel zooms, dragging with the right button pans in free mode
+    pub fn handle_ui(&mut self, ui: &Ui)
+    {
+        let io = ui.io();
+        self.set_viewport(io.display_size);
+        if io.want_capture_mouse {
+            return;
+        }
+        if io.mouse_wheel != 0.0f32 {
+            self.zoom_at(&io.mouse_pos, WHEEL_ZOOM_STEP.powf(io.mouse_wheel));
+        }
+        if self.mode == CameraMode::Free && ui.is_mouse_down(MouseButton::Right) {
+            self.pan(&io.mouse_delta);
+        }
+    }
+}
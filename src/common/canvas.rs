@@ -0,0 +1,142 @@
+use glium::implement_vertex;
+use std::path::Path;
+use crate::atlas::*;
+use crate::ecs::World;
+use crate::game::*;
+use crate::quad::ortho_matrix;
+
+
+// background behind everything, in srgb like all other colours
+pub const CLEAR_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
+
+#[derive(Copy, Clone, Debug)]
+pub struct QuadVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+implement_vertex!(QuadVertex, position, uv, color);
+
+
+// everything that gets drawn in a frame, independent of what ends up rasterizing it.
+// MyRenderer draws it with opengl, the offscreen module in software
+pub struct Canvas
+{
+    vertices: Vec<QuadVertex>,
+    atlas: SpriteAtlas,
+    player_sprites: Vec<SpriteId>,
+    view_matrix: [[f32; 4]; 4],
+}
+
+impl Default for Canvas {
+    fn default() -> Self {
+        Canvas::new()
+    }
+}
+
+impl Canvas
+{
+    pub fn new() -> Canvas
+    {
+        let mut atlas = SpriteAtlas::new();
+        let player_sprites = (0..PLAYER_SPRITE_COUNT)
+            .map(|i| atlas.add_rgba(PLAYER_SPRITE_SIZE, PLAYER_SPRITE_SIZE, &generate_player_sprite(i)).unwrap())
+            .collect();
+        Canvas {
+            vertices: Vec::new(),
+            atlas,
+            player_sprites,
+            view_matrix: ortho_matrix(0.0f32, GAME_AREA_WIDTH, 0.0f32, GAME_AREA_HEIGHT),
+        }
+    }
+
+    // quads are given in world coordinates and drawn before the ui in a single batch,
+    // WHITE_UV with a zero uv_size gives a plain coloured quad
+    pub fn add_quad(&mut self, pos_start: &[f32; 2], pos_size: &[f32; 2], uv_start: &[f32; 2], uv_size: &[f32; 2], base_color: &[f32; 4])
+    {
+        let [x0, y0] = *pos_start;
+        let [x1, y1] = [x0 + pos_size[0], y0 + pos_size[1]];
+        let [u0, v0] = *uv_start;
+        let [u1, v1] = [u0 + uv_size[0], v0 + uv_size[1]];
+        self.vertices.extend_from_slice(&[
+            QuadVertex { position: [x0, y0], uv: [u0, v0], color: *base_color },
+            QuadVertex { position: [x1, y0], uv: [u1, v0], color: *base_color },
+            QuadVertex { position: [x1, y1], uv: [u1, v1], color: *base_color },
+            QuadVertex { position: [x0, y1], uv: [u0, v1], color: *base_color },
+        ]);
+    }
+    // the sprite is tinted by base_color, unknown ids draw a plain quad
+    pub fn add_sprite(&mut self, pos_start: &[f32; 2], pos_size: &[f32; 2], sprite: SpriteId, base_color: &[f32; 4])
+    {
+        match self.atlas.get(sprite) {
+            Some(s) => {
+                let (uv_start, uv_size) = (s.uv_start, s.uv_size);
+                self.add_quad(pos_start, pos_size, &uv_start, &uv_size, base_color);
+            }
+            None => self.add_quad(pos_start, pos_size, &WHITE_UV, &[0.0, 0.0f32], base_color),
+        }
+    }
+    pub fn load_sprite<P: AsRef<Path>>(&mut self, path: P) -> Result<SpriteId, AtlasError>
+    {
+        self.atlas.load(path)
+    }
+    pub fn add_sprite_rgba(&mut self, width: u32, height: u32, rgba: &[u8]) -> Result<SpriteId, AtlasError>
+    {
+        self.atlas.add_rgba(width, height, rgba)
+    }
+    pub fn add_game_area(&mut self)
+    {
+        self.add_quad(&[0.0, 0.0f32], &[GAME_AREA_WIDTH, GAME_AREA_HEIGHT], &WHITE_UV, &[0.0, 0.0f32], &[0.25, 0.25, 0.25, 1.0]);
+    }
+    pub fn add_player(&mut self, player: &Player)
+    {
+        let sprite = self.player_sprites[(player.sprite % PLAYER_SPRITE_COUNT) as usize];
+        self.add_sprite(&player.pos, &[PLAYER_SIZE, PLAYER_SIZE], sprite, &player.col);
+    }
+    pub fn add_projectile(&mut self, projectile: &Projectile)
+    {
+        let half = PROJECTILE_SIZE * 0.5f32;
+        self.add_quad(&[projectile.pos[0] - half, projectile.pos[1] - half], &[PROJECTILE_SIZE, PROJECTILE_SIZE], &WHITE_UV, &[0.0, 0.0f32], &[1.0, 1.0, 0.6, 1.0]);
+    }
+    // the game area and everything in it, the same for server, client and screenshots
+    pub fn draw_world(&mut self, world: &World)
+    {
+        self.add_game_area();
+        for (_, p) in world.iter::<Player>() {
+            self.add_player(p);
+        }
+        for (_, proj) in world.iter::<Projectile>() {
+            self.add_projectile(proj);
+        }
+    }
+
+    // maps the quad coordinates to clip space
+    pub fn set_view_matrix(&mut self, matrix: [[f32; 4]; 4])
+    {
+        self.view_matrix = matrix;
+    }
+    pub fn view_matrix(&self) -> &[[f32; 4]; 4]
+    {
+        &self.view_matrix
+    }
+    pub fn vertices(&self) -> &[QuadVertex]
+    {
+        &self.vertices
+    }
+    pub fn quad_count(&self) -> usize
+    {
+        self.vertices.len() / 4
+    }
+    pub fn clear(&mut self)
+    {
+        self.vertices.clear();
+    }
+    pub fn atlas(&self) -> &SpriteAtlas
+    {
+        &self.atlas
+    }
+    pub fn atlas_mut(&mut self) -> &mut SpriteAtlas
+    {
+        &mut self.atlas
+    }
+}
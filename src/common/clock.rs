@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::game::NetworkMessages;
+
+
+// samples used for the estimate, at 1 request per second this covers about half a minute
+const MAX_CLOCK_SAMPLES: usize = 32;
+// the first few requests go out quickly so the client gets a usable estimate right away
+const FAST_SYNC_SAMPLES: usize = 8;
+const FAST_SYNC_INTERVAL: Duration = Duration::from_millis(100);
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+// drift beyond this is not a real clock but garbage in the samples
+const MAX_DRIFT: f64 = 0.001;
+// tick lengths the server can send, anything else would stall or panic the fixed timestep
+pub const MIN_TICK_DT: f32 = 0.001f32;
+pub const MAX_TICK_DT: f32 = 1.0f32;
+
+
+// false for NaN and infinity as well
+pub fn is_valid_tick_dt(tick_dt: f32) -> bool
+{
+    (MIN_TICK_DT..=MAX_TICK_DT).contains(&tick_dt)
+}
+
+// seconds since `start` on the monotonic clock
+pub fn clock_seconds(start: Instant) -> f64
+{
+    Instant::now().saturating_duration_since(start).as_secs_f64()
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClockSample
+{
+    // local time in the middle of the round trip
+    pub local_time: f64,
+    pub offset: f64,
+    pub rtt: f64,
+}
+
+
+// NTP style estimate of the server clock: every sample has four timestamps
+// t0 client send, t1 server receive, t2 server send, t3 client receive.
+// offset = ((t1 - t0) + (t2 - t3)) / 2 and rtt = (t3 - t0) - (t2 - t1).
+// Only the samples with the lowest rtt are trusted, a line through them gives offset and drift
+pub struct ClockSync
+{
+    start: Instant,
+    samples: VecDeque<ClockSample>,
+    total_samples: usize,
+    last_request: Option<Instant>,
+    offset: f64,
+    drift: f64,
+    reference_time: f64,
+    rtt: f64,
+    // the server tick that was current at server_send_time, with the servers tick length
+    last_server_tick: u64,
+    last_server_tick_time: f64,
+    tick_dt: f64,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        ClockSync::new()
+    }
+}
+
+impl ClockSync
+{
+    pub fn new() -> ClockSync
+    {
+        ClockSync {
+            start: Instant::now(),
+            samples: VecDeque::new(),
+            total_samples: 0,
+            last_request: None,
+            offset: 0.0f64,
+            drift: 0.0f64,
+            reference_time: 0.0f64,
+            rtt: 0.0f64,
+            last_server_tick: 0,
+            last_server_tick_time: 0.0f64,
+            tick_dt: 0.0f64,
+        }
+    }
+
+    pub fn local_time(&self) -> f64
+    {
+        clock_seconds(self.start)
+    }
+
+    // returns a request whenever the next one is due
+    pub fn poll_request(&mut self) -> Option<NetworkMessages>
+    {
+        let interval = if self.total_samples < FAST_SYNC_SAMPLES { FAST_SYNC_INTERVAL } else { SYNC_INTERVAL };
+        let now = Instant::now();
+        if let Some(last) = self.last_request {
+            if now.saturating_duration_since(last) < interval {
+                return None;
+            }
+        }
+        self.last_request = Some(now);
+        Some(NetworkMessages::ClockRequest { client_time: self.local_time(), server_receive_time: 0.0f64 })
+    }
+
+    pub fn handle_response(&mut self, client_time: f64, server_receive_time: f64, server_send_time: f64, server_tick: u64, tick_dt: f32)
+    {
+        let t3 = self.local_time();
+        let rtt = (t3 - client_time) - (server_send_time - server_receive_time);
+        if !rtt.is_finite() || rtt < 0.0f64 || client_time > t3 {
+            return;
+        }
+        let offset = ((server_receive_time - client_time) + (server_send_time - t3)) * 0.5f64;
+        self.samples.push_back(ClockSample { local_time: (client_time + t3) * 0.5f64, offset, rtt });
+        while self.samples.len() > MAX_CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.total_samples += 1;
+        self.last_server_tick = server_tick;
+        self.last_server_tick_time = server_send_time;
+        if is_valid_tick_dt(tick_dt) {
+            self.tick_dt = tick_dt as f64;
+        }
+        self.refine();
+    }
+
+    fn refine(&mut self)
+    {
+        let min_rtt = self.samples.iter().map(|s| s.rtt).fold(f64::MAX, f64::min);
+        // a bit of slack so there is more than one sample to fit through
+        let max_rtt = min_rtt * 1.5f64 + 0.002f64;
+        let good: Vec<ClockSample> = self.samples.iter().copied().filter(|s| s.rtt <= max_rtt).collect();
+
+        let n = good.len() as f64;
+        let mean_t = good.iter().map(|s| s.local_time).sum::<f64>() / n;
+        let mean_o = good.iter().map(|s| s.offset).sum::<f64>() / n;
+        let var_t = good.iter().map(|s| (s.local_time - mean_t) * (s.local_time - mean_t)).sum::<f64>();
+        let cov = good.iter().map(|s| (s.local_time - mean_t) * (s.offset - mean_o)).sum::<f64>();
+
+        // the drift only means something once the samples span a few seconds
+        self.drift = if good.len() >= 4 && var_t > n * 1.0f64 { (cov / var_t).clamp(-MAX_DRIFT, MAX_DRIFT) } else { 0.0f64 };
+        self.offset = mean_o;
+        self.reference_time = mean_t;
+        self.rtt = good.iter().map(|s| s.rtt).sum::<f64>() / n;
+    }
+
+    pub fn is_synced(&self) -> bool
+    {
+        self.total_samples > 0
+    }
+    pub fn offset(&self) -> f64
+    {
+        self.offset + self.drift * (self.local_time() - self.reference_time)
+    }
+    // seconds per second the server clock runs faster than ours
+    pub fn drift(&self) -> f64
+    {
+        self.drift
+    }
+    pub fn rtt(&self) -> f64
+    {
+        self.rtt
+    }
+    pub fn server_time(&self) -> f64
+    {
+        self.local_time() + self.offset()
+    }
+    // seconds per server tick, None until the first response
+    pub fn tick_dt(&self) -> Option<f32>
+    {
+        Some(self.tick_dt as f32).filter(|dt| is_valid_tick_dt(*dt))
+    }
+    // the tick the server is simulating right now, fractional
+    pub fn server_tick(&self) -> f64
+    {
+        if self.tick_dt().is_none() {
+            return self.last_server_tick as f64;
+        }
+        self.last_server_tick as f64 + (self.server_time() - self.last_server_tick_time) / self.tick_dt
+    }
+}
@@ -0,0 +1,56 @@
+// optional lz4 compression of a connection, asked for in Hello and confirmed in Welcome.
+// Once it is on every frame payload starts with a flag byte telling whether the message
+// after it is compressed, messages below COMPRESSION_THRESHOLD are sent as they are
+use std::io;
+use crate::net::{FRAME_HEADER_SIZE, MAX_MESSAGE_SIZE};
+
+
+// small messages like single position updates gain nothing from compression
+pub const COMPRESSION_THRESHOLD: usize = 256;
+const UNCOMPRESSED: u8 = 0;
+// followed by the uncompressed size as little endian u32 and the lz4 block
+const LZ4: u8 = 1;
+
+
+// turns a frame from encode_message into a frame with the flag byte
+pub fn compress_frame(frame: &[u8]) -> Vec<u8>
+{
+    let payload = &frame[FRAME_HEADER_SIZE..];
+    let compressed = if payload.len() >= COMPRESSION_THRESHOLD {
+        Some(lz4_flex::compress_prepend_size(payload)).filter(|c| c.len() < payload.len())
+    }
+    else {
+        None
+    };
+    let (flag, data) = match &compressed {
+        Some(c) => (LZ4, c.as_slice()),
+        None => (UNCOMPRESSED, payload),
+    };
+    let mut out = Vec::with_capacity(FRAME_HEADER_SIZE + 1 + data.len());
+    out.extend_from_slice(&((data.len() + 1) as u32).to_le_bytes());
+    out.push(flag);
+    out.extend_from_slice(data);
+    out
+}
+
+// the message of a payload with the flag byte, the size stored in front of the lz4 block
+// is checked before anything is allocated
+pub fn decompress_payload(payload: &[u8]) -> io::Result<Vec<u8>>
+{
+    let invalid = |text: &str| io::Error::new(io::ErrorKind::InvalidData, text.to_string());
+    match payload.split_first() {
+        Some((&UNCOMPRESSED, data)) => Ok(data.to_vec()),
+        Some((&LZ4, data)) if data.len() >= 4 => {
+            let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            if size > MAX_MESSAGE_SIZE {
+                return Err(invalid("compressed message is too large"));
+            }
+            let mut out = vec![0u8; size];
+            match lz4_flex::decompress_into(&data[4..], &mut out) {
+                Ok(n) if n == size => Ok(out),
+                _ => Err(invalid("invalid compressed message")),
+            }
+        }
+        _ => Err(invalid("invalid compression flag")),
+    }
+}
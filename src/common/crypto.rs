@@ -0,0 +1,191 @@
+// optional encryption of a connection with Noise. The client starts every connection with
+// Hello, if it asks for encryption both sides run the Noise XX handshake in Handshake
+// messages and every frame after that is encrypted, only the length prefix stays readable.
+// The server has a static key the clients can pin, the client key is new every connection
+use std::io::{self, prelude::*};
+use std::sync::Arc;
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use crate::game::NetworkMessages;
+use crate::net::*;
+
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+// binds the handshake to this protocol
+const NOISE_PROLOGUE: &[u8] = b"simple_networking";
+const MAX_NOISE_MESSAGE: usize = 65535;
+const TAG_SIZE: usize = 16;
+// longer frames are encrypted in several pieces, each one is a noise message of its own
+const MAX_CHUNK: usize = MAX_NOISE_MESSAGE - TAG_SIZE;
+
+
+pub fn to_hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+pub fn from_hex(text: &str) -> Option<Vec<u8>>
+{
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+fn builder() -> Builder<'static>
+{
+    Builder::new(NOISE_PARAMS.parse().unwrap()).prologue(NOISE_PROLOGUE)
+}
+fn noise_error(e: snow::Error) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+
+// the static key pair of the server
+pub struct NoiseKey
+{
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl NoiseKey {
+    pub fn generate() -> NoiseKey
+    {
+        let keypair = builder().generate_keypair().unwrap();
+        NoiseKey { private: keypair.private, public: keypair.public }
+    }
+    // the file holds the private and the public key in hex, one per line.
+    // A missing file is created with a new key so encryption works without any setup
+    pub fn load_or_create(path: &str) -> Result<NoiseKey, String>
+    {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let keys: Vec<Vec<u8>> = text.lines().filter_map(from_hex).collect();
+                match keys.as_slice() {
+                    [private, public] if private.len() == 32 && public.len() == 32 => {
+                        Ok(NoiseKey { private: private.clone(), public: public.clone() })
+                    }
+                    _ => Err(format!("{} is not a key file", path)),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = NoiseKey::generate();
+                key.save(path).map_err(|e| format!("could not write the key to {}: {}", path, e))?;
+                Ok(key)
+            }
+            Err(e) => Err(format!("could not read the key {}: {}", path, e)),
+        }
+    }
+    fn save(&self, path: &str) -> io::Result<()>
+    {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // only the owner can read the private key
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        writeln!(file, "{}\n{}", to_hex(&self.private), to_hex(&self.public))
+    }
+    // what clients put in their config to make sure they talk to this server
+    pub fn public_hex(&self) -> String
+    {
+        to_hex(&self.public)
+    }
+}
+
+
+// encrypts the frames of one direction, each direction counts its own nonces so the
+// reading and the writing half of a connection can live on different threads
+pub struct SendCipher
+{
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+pub struct ReceiveCipher
+{
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl SendCipher {
+    // turns a frame from encode_message into an encrypted frame
+    pub fn seal(&mut self, frame: &[u8]) -> Vec<u8>
+    {
+        let payload = &frame[FRAME_HEADER_SIZE..];
+        let mut out = vec![0u8; FRAME_HEADER_SIZE + payload.len() + payload.len().div_ceil(MAX_CHUNK) * TAG_SIZE];
+        let mut size = FRAME_HEADER_SIZE;
+        for chunk in payload.chunks(MAX_CHUNK) {
+            size += self.transport.write_message(self.nonce, chunk, &mut out[size..]).unwrap();
+            self.nonce += 1;
+        }
+        out[..FRAME_HEADER_SIZE].copy_from_slice(&((size - FRAME_HEADER_SIZE) as u32).to_le_bytes());
+        out
+    }
+}
+
+impl ReceiveCipher {
+    // the payload of an encrypted frame, anything that does not decrypt is an error
+    pub fn open(&mut self, payload: &[u8]) -> io::Result<Vec<u8>>
+    {
+        let mut out = vec![0u8; payload.len()];
+        let mut size = 0;
+        for chunk in payload.chunks(MAX_NOISE_MESSAGE) {
+            size += self.transport.read_message(self.nonce, chunk, &mut out[size..]).map_err(noise_error)?;
+            self.nonce += 1;
+        }
+        out.truncate(size);
+        Ok(out)
+    }
+}
+
+fn split(handshake: HandshakeState) -> io::Result<(SendCipher, ReceiveCipher)>
+{
+    let transport = Arc::new(handshake.into_stateless_transport_mode().map_err(noise_error)?);
+    Ok((SendCipher { transport: transport.clone(), nonce: 0 }, ReceiveCipher { transport, nonce: 0 }))
+}
+
+fn write_handshake<W: Write>(stream: &mut W, handshake: &mut HandshakeState) -> io::Result<()>
+{
+    let mut data = vec![0u8; MAX_NOISE_MESSAGE];
+    let size = handshake.write_message(&[], &mut data).map_err(noise_error)?;
+    data.truncate(size);
+    send_message(stream, &NetworkMessages::Handshake{ data })
+}
+fn read_handshake<R: Read>(stream: &mut R, reader: &mut MessageReader, handshake: &mut HandshakeState) -> io::Result<()>
+{
+    match reader.read_message(stream)? {
+        NetworkMessages::Handshake{ data } => {
+            let mut payload = vec![0u8; MAX_NOISE_MESSAGE];
+            handshake.read_message(&data, &mut payload).map_err(noise_error)?;
+            Ok(())
+        }
+        // e.g. the address is banned
+        NetworkMessages::Kicked{ reason } => Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason)),
+        msg => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected Handshake, got {}", msg.name()))),
+    }
+}
+
+// the client side, returns the ciphers and the public key of the server
+pub fn initiate_handshake<S: Read + Write>(stream: &mut S, reader: &mut MessageReader) -> io::Result<(SendCipher, ReceiveCipher, Vec<u8>)>
+{
+    let key = NoiseKey::generate();
+    let mut handshake = builder().local_private_key(&key.private).build_initiator().map_err(noise_error)?;
+    // -> e
+    write_handshake(stream, &mut handshake)?;
+    // <- e, ee, s, es
+    read_handshake(stream, reader, &mut handshake)?;
+    // -> s, se
+    write_handshake(stream, &mut handshake)?;
+    let server_key = handshake.get_remote_static().unwrap_or_default().to_vec();
+    let (send, receive) = split(handshake)?;
+    Ok((send, receive, server_key))
+}
+
+// the server side after the client asked for encryption in its Hello
+pub fn accept_handshake<S: Read + Write>(stream: &mut S, reader: &mut MessageReader, key: &NoiseKey) -> io::Result<(SendCipher, ReceiveCipher)>
+{
+    let mut handshake = builder().local_private_key(&key.private).build_responder().map_err(noise_error)?;
+    read_handshake(stream, reader, &mut handshake)?;
+    write_handshake(stream, &mut handshake)?;
+    read_handshake(stream, reader, &mut handshake)?;
+    split(handshake)
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+use imgui::{CollapsingHeader, Ui};
+use crate::game::NetworkMessages;
+
+
+// samples kept for the scrolling plots, at SAMPLE_INTERVAL this is 30 seconds
+pub const PLOT_SAMPLES: usize = 120;
+// seconds between two rate samples
+const SAMPLE_INTERVAL: f32 = 0.25f32;
+const PLOT_HEIGHT: f32 = 60.0f32;
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Traffic
+{
+    pub bytes: u64,
+    pub messages: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, bytes: usize)
+    {
+        self.bytes += bytes as u64;
+        self.messages += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rate
+{
+    pub bytes_per_sec: f32,
+    pub messages_per_sec: f32,
+}
+
+
+// fixed length history of values, the oldest one drops out
+#[derive(Debug, Clone, Default)]
+pub struct Plot
+{
+    values: Vec<f32>,
+}
+
+impl Plot {
+    pub fn push(&mut self, value: f32)
+    {
+        if self.values.len() == PLOT_SAMPLES {
+            self.values.remove(0);
+        }
+        self.values.push(value);
+    }
+    pub fn values(&self) -> &[f32]
+    {
+        &self.values
+    }
+    pub fn last(&self) -> f32
+    {
+        self.values.last().copied().unwrap_or(0.0f32)
+    }
+    pub fn max(&self) -> f32
+    {
+        self.values.iter().copied().fold(0.0f32, f32::max)
+    }
+    pub fn draw(&self, ui: &Ui, label: &str, overlay: &str)
+    {
+        let width = ui.content_region_avail()[0];
+        ui.plot_lines(label, &self.values)
+            .overlay_text(overlay)
+            .scale_min(0.0f32)
+            .graph_size([width, PLOT_HEIGHT])
+            .build();
+    }
+}
+
+
+// traffic of one side of the connection(s), shared between the reader threads and the tick.
+// Sizes are the encoded sizes of the messages, without tcp overhead
+pub struct NetStats
+{
+    pub sent: Traffic,
+    pub received: Traffic,
+    pub sent_by_type: BTreeMap<&'static str, Traffic>,
+    pub received_by_type: BTreeMap<&'static str, Traffic>,
+    pub send_rate: Rate,
+    pub receive_rate: Rate,
+    // kilobytes per second
+    pub send_plot: Plot,
+    pub receive_plot: Plot,
+    window_start: Instant,
+    window_sent: Traffic,
+    window_received: Traffic,
+}
+
+impl Default for NetStats {
+    fn default() -> Self {
+        NetStats::new()
+    }
+}
+
+impl NetStats {
+    pub fn new() -> NetStats
+    {
+        NetStats {
+            sent: Traffic::default(),
+            received: Traffic::default(),
+            sent_by_type: BTreeMap::new(),
+            received_by_type: BTreeMap::new(),
+            send_rate: Rate::default(),
+            receive_rate: Rate::default(),
+            send_plot: Plot::default(),
+            receive_plot: Plot::default(),
+            window_start: Instant::now(),
+            window_sent: Traffic::default(),
+            window_received: Traffic::default(),
+        }
+    }
+
+    pub fn record_sent(&mut self, msg: &NetworkMessages, bytes: usize)
+    {
+        self.sent.add(bytes);
+        self.window_sent.add(bytes);
+        self.sent_by_type.entry(msg.name()).or_default().add(bytes);
+    }
+    pub fn record_received(&mut self, msg: &NetworkMessages, bytes: usize)
+    {
+        self.received.add(bytes);
+        self.window_received.add(bytes);
+        self.received_by_type.entry(msg.name()).or_default().add(bytes);
+    }
+
+    // turns the traffic since the last sample into rates, call it at least every frame
+    pub fn update(&mut self)
+    {
+        let elapsed = self.window_start.elapsed().as_secs_f32();
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let rate = |t: &Traffic| Rate { bytes_per_sec: t.bytes as f32 / elapsed, messages_per_sec: t.messages as f32 / elapsed };
+        self.send_rate = rate(&self.window_sent);
+        self.receive_rate = rate(&self.window_received);
+        self.send_plot.push(self.send_rate.bytes_per_sec / 1024.0f32);
+        self.receive_plot.push(self.receive_rate.bytes_per_sec / 1024.0f32);
+        self.window_sent = Traffic::default();
+        self.window_received = Traffic::default();
+        self.window_start = Instant::now();
+    }
+
+    pub fn draw(&self, ui: &Ui)
+    {
+        ui.text(format!(
+            "In:  {:.2} KB/s {:.0} msg/s ({} msgs, {:.1} KB total)",
+            self.receive_rate.bytes_per_sec / 1024.0f32, self.receive_rate.messages_per_sec,
+            self.received.messages, self.received.bytes as f32 / 1024.0f32
+        ));
+        ui.text(format!(
+            "Out: {:.2} KB/s {:.0} msg/s ({} msgs, {:.1} KB total)",
+            self.send_rate.bytes_per_sec / 1024.0f32, self.send_rate.messages_per_sec,
+            self.sent.messages, self.sent.bytes as f32 / 1024.0f32
+        ));
+        self.receive_plot.draw(ui, "##in", &format!("in {:.2} KB/s (max {:.2})", self.receive_plot.last(), self.receive_plot.max()));
+        self.send_plot.draw(ui, "##out", &format!("out {:.2} KB/s (max {:.2})", self.send_plot.last(), self.send_plot.max()));
+
+        if CollapsingHeader::new("Message types").build(ui) {
+            ui.columns(5, "message_types", true);
+            for name in ["Message", "In", "In KB", "Out", "Out KB"] {
+                ui.text(name);
+                ui.next_column();
+            }
+            ui.separator();
+            let mut names: Vec<&'static str> = self.received_by_type.keys().chain(self.sent_by_type.keys()).copied().collect();
+            names.sort_unstable();
+            names.dedup();
+            for name in names {
+                let received = self.received_by_type.get(name).copied().unwrap_or_default();
+                let sent = self.sent_by_type.get(name).copied().unwrap_or_default();
+                for cell in [
+                    name.to_string(),
+                    received.messages.to_string(),
+                    format!("{:.1}", received.bytes as f32 / 1024.0f32),
+                    sent.messages.to_string(),
+                    format!("{:.1}", sent.bytes as f32 / 1024.0f32),
+                ] {
+                    ui.text(cell);
+                    ui.next_column();
+                }
+            }
+            ui.columns(1, "message_types", false);
+        }
+    }
+}
+
+
+// how long the fixed updates take and how regularly they run, in milliseconds
+#[derive(Debug, Clone, Default)]
+pub struct TickTiming
+{
+    last_start: Option<Instant>,
+    pub interval_plot: Plot,
+    pub duration_plot: Plot,
+    pub max_duration: f32,
+}
+
+impl TickTiming {
+    pub fn begin(&mut self) -> Instant
+    {
+        let now = Instant::now();
+        if let Some(last) = self.last_start {
+            self.interval_plot.push(now.duration_since(last).as_secs_f32() * 1000.0f32);
+        }
+        self.last_start = Some(now);
+        now
+    }
+    pub fn end(&mut self, start: Instant)
+    {
+        let duration = start.elapsed().as_secs_f32() * 1000.0f32;
+        self.max_duration = self.max_duration.max(duration);
+        self.duration_plot.push(duration);
+    }
+    pub fn draw(&self, ui: &Ui)
+    {
+        self.interval_plot.draw(ui, "##tick_interval", &format!("tick interval {:.2}ms", self.interval_plot.last()));
+        self.duration_plot.draw(ui, "##tick_duration", &format!("tick duration {:.3}ms (max {:.3})", self.duration_plot.last(), self.max_duration));
+    }
+}
@@ -0,0 +1,259 @@
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use tracing::debug;
+use crate::ids::IdAllocator;
+
+
+pub type Entity = u32;
+pub type ComponentKind = u16;
+
+// anything that is 'static and can be moved between threads can be stored in the world
+pub trait Component: Any + Send {}
+impl<T: Any + Send> Component for T {}
+
+// components that are synced from the server to every client
+// KIND has to be unique for every registered type, it is what goes over the wire
+pub trait Replicated: Component + Serialize + DeserializeOwned {
+    const KIND: ComponentKind;
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ComponentUpdate {
+    pub entity: Entity,
+    pub kind: ComponentKind,
+    pub data: Vec<u8>,
+}
+
+
+struct Storage<T> {
+    components: BTreeMap<Entity, T>,
+    changed: BTreeSet<Entity>,
+}
+
+trait AnyStorage: Send {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Component> AnyStorage for Storage<T> {
+    fn remove_entity(&mut self, entity: Entity)
+    {
+        self.components.remove(&entity);
+        self.changed.remove(&entity);
+    }
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+
+#[derive(Default)]
+pub struct World {
+    entities: BTreeSet<Entity>,
+    ids: IdAllocator,
+    storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+}
+
+impl World {
+    pub fn new() -> World
+    {
+        World::default()
+    }
+
+    // the id has a new generation if its slot was used before, see ids.rs
+    pub fn spawn(&mut self) -> Entity
+    {
+        let id = self.ids.allocate();
+        self.entities.insert(id);
+        id
+    }
+    // used when the id is dictated from the outside (e.g. by the server), returns false if it was
+    // already alive or is stale. Don't mix it with spawn in the same world
+    pub fn spawn_at(&mut self, entity: Entity) -> bool
+    {
+        if self.ids.is_stale(entity) {
+            return false;
+        }
+        // the slot was handed out again, so whatever had it before is gone even if its despawn got lost
+        if let Some(old) = self.ids.alive_at(entity).filter(|old| *old != entity) {
+            self.despawn(old);
+        }
+        self.ids.claim(entity) && self.entities.insert(entity)
+    }
+    pub fn despawn(&mut self, entity: Entity) -> bool
+    {
+        if !self.entities.remove(&entity) {
+            return false;
+        }
+        self.ids.free(entity);
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        }
+        true
+    }
+    pub fn contains(&self, entity: Entity) -> bool
+    {
+        self.entities.contains(&entity)
+    }
+    // the entity was despawned, its id may already belong to another one
+    pub fn is_stale(&self, entity: Entity) -> bool
+    {
+        self.ids.is_stale(entity)
+    }
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_
+    {
+        self.entities.iter().copied()
+    }
+
+
+    fn storage<T: Component>(&self) -> Option<&Storage<T>>
+    {
+        self.storages.get(&TypeId::of::<T>()).and_then(|s| s.as_any().downcast_ref::<Storage<T>>())
+    }
+    fn storage_mut<T: Component>(&mut self) -> &mut Storage<T>
+    {
+        self.storages.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Storage::<T> { components: BTreeMap::new(), changed: BTreeSet::new() }))
+            .as_any_mut().downcast_mut::<Storage<T>>().unwrap()
+    }
+
+    // inserting marks the component as changed, returns the previous value if there was one
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> Option<T>
+    {
+        if !self.entities.contains(&entity) {
+            return Some(component);
+        }
+        let storage = self.storage_mut::<T>();
+        storage.changed.insert(entity);
+        storage.components.insert(entity, component)
+    }
+    pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T>
+    {
+        let storage = self.storage_mut::<T>();
+        storage.changed.remove(&entity);
+        storage.components.remove(&entity)
+    }
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T>
+    {
+        self.storage::<T>().and_then(|s| s.components.get(&entity))
+    }
+    // mutable access always marks the component as changed
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T>
+    {
+        let storage = self.storage_mut::<T>();
+        let c = storage.components.get_mut(&entity)?;
+        storage.changed.insert(entity);
+        Some(c)
+    }
+    pub fn has<T: Component>(&self, entity: Entity) -> bool
+    {
+        self.get::<T>(entity).is_some()
+    }
+    pub fn iter<T: Component>(&self) -> impl Iterator<Item = (Entity, &T)>
+    {
+        self.storage::<T>().into_iter().flat_map(|s| s.components.iter().map(|(e, c)| (*e, c)))
+    }
+    // collects the ids up front so the caller can freely get_mut while iterating
+    pub fn entities_with<T: Component>(&self) -> Vec<Entity>
+    {
+        self.iter::<T>().map(|(e, _)| e).collect()
+    }
+    pub fn take_changed<T: Component>(&mut self) -> Vec<Entity>
+    {
+        let storage = self.storage_mut::<T>();
+        let changed = std::mem::take(&mut storage.changed);
+        changed.into_iter().filter(|e| storage.components.contains_key(e)).collect()
+    }
+}
+
+
+
+
+struct ReplicationHandler {
+    kind: ComponentKind,
+    collect_changed: fn(&mut World, &mut Vec<ComponentUpdate>),
+    collect_all: fn(&World, &mut Vec<ComponentUpdate>),
+    apply: fn(&mut World, &ComponentUpdate) -> bool,
+}
+
+fn collect_changed<T: Replicated>(world: &mut World, out: &mut Vec<ComponentUpdate>)
+{
+    for entity in world.take_changed::<T>() {
+        let c = world.get::<T>(entity).unwrap();
+        out.push(ComponentUpdate { entity, kind: T::KIND, data: bincode::serialize(c).unwrap() });
+    }
+}
+fn collect_all<T: Replicated>(world: &World, out: &mut Vec<ComponentUpdate>)
+{
+    for (entity, c) in world.iter::<T>() {
+        out.push(ComponentUpdate { entity, kind: T::KIND, data: bincode::serialize(c).unwrap() });
+    }
+}
+fn apply<T: Replicated>(world: &mut World, update: &ComponentUpdate) -> bool
+{
+    if !world.contains(update.entity) && !world.spawn_at(update.entity) {
+        debug!(entity = update.entity, "dropping an update for a stale entity");
+        return false;
+    }
+    match bincode::deserialize::<T>(&update.data) {
+        Ok(c) => {
+            world.insert(update.entity, c);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+
+// table of every replicated component type, both sides have to register the same types
+#[derive(Default)]
+pub struct Replication {
+    handlers: Vec<ReplicationHandler>,
+}
+
+impl Replication {
+    pub fn new() -> Replication
+    {
+        Replication::default()
+    }
+    pub fn register<T: Replicated>(&mut self) -> &mut Self
+    {
+        assert!(self.handlers.iter().all(|h| h.kind != T::KIND), "component kind {} registered twice", T::KIND);
+        self.handlers.push(ReplicationHandler {
+            kind: T::KIND,
+            collect_changed: collect_changed::<T>,
+            collect_all: collect_all::<T>,
+            apply: apply::<T>,
+        });
+        self
+    }
+    // every replicated component that changed since the last call
+    pub fn collect_changes(&self, world: &mut World) -> Vec<ComponentUpdate>
+    {
+        let mut out = Vec::new();
+        for h in &self.handlers {
+            (h.collect_changed)(world, &mut out);
+        }
+        out
+    }
+    // the full replicated state, used for newly joined clients
+    pub fn snapshot(&self, world: &World) -> Vec<ComponentUpdate>
+    {
+        let mut out = Vec::new();
+        for h in &self.handlers {
+            (h.collect_all)(world, &mut out);
+        }
+        out
+    }
+    // returns false for unknown kinds, stale entities or data that does not deserialize
+    pub fn apply(&self, world: &mut World, update: &ComponentUpdate) -> bool
+    {
+        match self.handlers.iter().find(|h| h.kind == update.kind) {
+            Some(h) => (h.apply)(world, update),
+            None => false,
+        }
+    }
+}
@@ -1,42 +1,163 @@
-use std::thread;
-use std::sync::Mutex;
 use rand::distributions::{Distribution, Uniform};
 extern crate bincode;
 use serde::{Serialize, Deserialize};
+use crate::ecs::*;
+use crate::atlas::PLAYER_SPRITE_COUNT;
 
 
 pub const GAME_AREA_WIDTH: f32 = 1000.0f32;
 pub const GAME_AREA_HEIGHT: f32 = 1000.0f32;
 pub const PLAYER_SIZE: f32 = 20.0f32;
 pub const TICK_RATE: f32 = 1.0f32 / 30.0f32;
+pub const PROJECTILE_SIZE: f32 = 6.0f32;
+pub const PROJECTILE_SPEED: f32 = 900.0f32;
+pub const PROJECTILE_LIFETIME: f32 = 1.0f32;
+pub const FIRE_COOLDOWN: f32 = 0.25f32;
+// the server will not rewind further back than this when resolving shots
+pub const MAX_REWIND_TIME: f64 = 1.0f64;
+// every input message repeats this many of the latest inputs so single lost packets do not matter
+pub const INPUT_REDUNDANCY: usize = 8;
 const SPAWN_PADDING: f32 = 10.0f32;
 
 #[derive(Default,Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Player {
-    pub id: u32,
+    pub id: Entity,
     pub cur_sequence_id: u32,
     pub pos: [f32; 2],
     pub col: [f32; 4],
+    // index into the built in player sprites
+    pub sprite: u32,
+    pub score: u32,
 }
 
 #[derive(Default,Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct PlayerInput {
-    pub id: u32,
+    pub id: Entity,
     pub cur_sequence_id: u32,
     pub up_down: f32,
     pub left_right: f32,
 }
 
+// a single shot, view_tick is the server tick of the world state the client saw when firing
+#[derive(Default,Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct FireInput {
+    pub id: Entity,
+    pub cur_sequence_id: u32,
+    pub dir: [f32; 2],
+    pub view_tick: f64,
+}
+
+#[derive(Default,Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Projectile {
+    pub owner: Entity,
+    pub pos: [f32; 2],
+    pub vel: [f32; 2],
+    pub time_left: f32,
+}
+
+
+// the area everything happens in, always GAME_AREA_WIDTH x GAME_AREA_HEIGHT for now
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct MapInfo {
+    pub width: f32,
+    pub height: f32,
+}
+impl Default for MapInfo {
+    fn default() -> Self {
+        MapInfo { width: GAME_AREA_WIDTH, height: GAME_AREA_HEIGHT }
+    }
+}
+
+// what the client has to follow to predict like the server simulates
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ServerSettings {
+    pub tick_dt: f32,
+    pub fire_cooldown: f32,
+}
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings { tick_dt: TICK_RATE, fire_cooldown: FIRE_COOLDOWN }
+    }
+}
+
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NetworkMessages
 {
     InvalidMessage,
-    AddLocal(Player),
+    // everything a client needs when it joins in one message, updates is the snapshot of every
+    // replicated component including the local player at tick, applied like a WorldUpdate
+    WorldState{local_id: Entity, tick: u64, updates: Vec<ComponentUpdate>, map: MapInfo, settings: ServerSettings},
     AddPlayer(Player),
-    RemovePlayer{id: u32},
-    ClientInputChange(PlayerInput),
-    Position(Player),
+    RemovePlayer{id: Entity},
+    // one input per client tick, the latest INPUT_REDUNDANCY of them oldest first
+    ClientInput{inputs: Vec<PlayerInput>},
+    ClientFire(FireInput),
+    WorldUpdate{tick: u64, updates: Vec<ComponentUpdate>},
+    DespawnEntity{id: Entity},
+    // server_receive_time is filled in by the server when the request arrives
+    ClockRequest{client_time: f64, server_receive_time: f64},
+    ClockResponse{client_time: f64, server_receive_time: f64, server_send_time: f64, server_tick: u64, tick_dt: f32},
+    // sent by the server to measure the round trip, the client echoes the time back in a Pong
+    Ping{server_time: f64},
+    Pong{server_time: f64},
+    // the server closes the connection right after
+    Kicked{reason: String},
+    // a frozen player can neither move nor shoot
+    SetFrozen{frozen: bool},
+    // an announcement from the server admin
+    ServerMessage{text: String},
+    // the server only lets the client join when the token is valid
+    Authenticate{user: String, token: String},
+    // the first message of every client, with encrypt set the noise handshake follows.
+    // The server answers with Welcome, compression is only on when both want it
+    Hello{encrypt: bool, compress: bool},
+    Handshake{data: Vec<u8>},
+    Welcome{compress: bool},
+}
+
+impl NetworkMessages {
+    // for statistics and logs
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            NetworkMessages::InvalidMessage => "InvalidMessage",
+            NetworkMessages::WorldState{..} => "WorldState",
+            NetworkMessages::AddPlayer(_) => "AddPlayer",
+            NetworkMessages::RemovePlayer{..} => "RemovePlayer",
+            NetworkMessages::ClientInput{..} => "ClientInput",
+            NetworkMessages::ClientFire(_) => "ClientFire",
+            NetworkMessages::WorldUpdate{..} => "WorldUpdate",
+            NetworkMessages::DespawnEntity{..} => "DespawnEntity",
+            NetworkMessages::ClockRequest{..} => "ClockRequest",
+            NetworkMessages::ClockResponse{..} => "ClockResponse",
+            NetworkMessages::Ping{..} => "Ping",
+            NetworkMessages::Pong{..} => "Pong",
+            NetworkMessages::Kicked{..} => "Kicked",
+            NetworkMessages::SetFrozen{..} => "SetFrozen",
+            NetworkMessages::ServerMessage{..} => "ServerMessage",
+            NetworkMessages::Authenticate{..} => "Authenticate",
+            NetworkMessages::Hello{..} => "Hello",
+            NetworkMessages::Handshake{..} => "Handshake",
+            NetworkMessages::Welcome{..} => "Welcome",
+        }
+    }
+}
+
+impl Replicated for Player {
+    const KIND: ComponentKind = 0;
+}
+impl Replicated for Projectile {
+    const KIND: ComponentKind = 1;
+}
+
+// every component type the server replicates, has to be identical on both sides
+pub fn create_replication() -> Replication
+{
+    let mut replication = Replication::new();
+    replication.register::<Player>();
+    replication.register::<Projectile>();
+    replication
 }
 
 
@@ -47,13 +168,52 @@ impl Player
     {
         self.pos[0] += input.left_right * dt * 300.0f32;
         self.pos[1] += input.up_down * dt * 300.0f32;
-        self.pos[0] = f32::max(f32::min(self.pos[0], GAME_AREA_WIDTH-10.0f32), 0.0f32);
-        self.pos[1] = f32::max(f32::min(self.pos[1], GAME_AREA_HEIGHT-10.0f32), 0.0f32);
+        self.pos[0] = self.pos[0].clamp(0.0f32, GAME_AREA_WIDTH-10.0f32);
+        self.pos[1] = self.pos[1].clamp(0.0f32, GAME_AREA_HEIGHT-10.0f32);
+    }
+    pub fn center(&self) -> [f32; 2]
+    {
+        [self.pos[0] + PLAYER_SIZE * 0.5f32, self.pos[1] + PLAYER_SIZE * 0.5f32]
+    }
+}
+
+impl Projectile
+{
+    // returns None for a zero direction
+    pub fn new(owner: Entity, pos: [f32; 2], dir: [f32; 2]) -> Option<Projectile>
+    {
+        let len = (dir[0] * dir[0] + dir[1] * dir[1]).sqrt();
+        if !len.is_finite() || len <= f32::EPSILON {
+            return None;
+        }
+        Some(Projectile {
+            owner,
+            pos,
+            vel: [dir[0] / len * PROJECTILE_SPEED, dir[1] / len * PROJECTILE_SPEED],
+            time_left: PROJECTILE_LIFETIME,
+        })
+    }
+    // returns false once the projectile expired or left the game area
+    pub fn update(&mut self, dt: f32) -> bool
+    {
+        self.pos[0] += self.vel[0] * dt;
+        self.pos[1] += self.vel[1] * dt;
+        self.time_left -= dt;
+        self.time_left > 0.0f32
+            && self.pos[0] >= 0.0f32 && self.pos[0] <= GAME_AREA_WIDTH
+            && self.pos[1] >= 0.0f32 && self.pos[1] <= GAME_AREA_HEIGHT
+    }
+    // pos is the top left corner of the player, like everywhere else
+    pub fn hits(&self, player_pos: &[f32; 2]) -> bool
+    {
+        let half = PROJECTILE_SIZE * 0.5f32;
+        self.pos[0] + half >= player_pos[0] && self.pos[0] - half <= player_pos[0] + PLAYER_SIZE
+            && self.pos[1] + half >= player_pos[1] && self.pos[1] - half <= player_pos[1] + PLAYER_SIZE
     }
 }
 
 
-pub fn create_random_player(id: u32) -> Player
+pub fn create_random_player(id: Entity) -> Player
 {
     let range_x = Uniform::new(0.0f32 + SPAWN_PADDING, GAME_AREA_WIDTH - SPAWN_PADDING);
     let range_y = Uniform::new(0.0f32 + SPAWN_PADDING, GAME_AREA_HEIGHT - SPAWN_PADDING);
@@ -68,18 +228,14 @@ pub fn create_random_player(id: u32) -> Player
     let rand_r = range_col.sample(&mut rng);
     let rand_g = range_col.sample(&mut rng);
     let rand_b = range_col.sample(&mut rng);
+    let rand_sprite = Uniform::new(0, PLAYER_SPRITE_COUNT).sample(&mut rng);
     
     Player {
-        id: id,
+        id,
         cur_sequence_id: 0,
         pos: [rand_x, rand_y],
         col: [rand_r, rand_g, rand_b, 1.0f32],
+        sprite: rand_sprite,
+        score: 0,
     }
 }
-
-
-pub fn world_to_screen(screen_sz: &[f32; 2], pos: &[f32; 2]) -> [f32; 2]
-{
-    let res = [ pos[0] * (screen_sz[0] / GAME_AREA_WIDTH), pos[1] * (screen_sz[1] / GAME_AREA_WIDTH) ];
-    res
-}
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use imgui::Ui;
+use crate::timestep::FixedTimestep;
+use crate::game::TICK_RATE;
+use crate::MyRenderer;
+
+
+// the three parts of a frame, split so the simulation does not depend on the window
+pub trait Game: Send + 'static {
+    // networking and simulation, called at tick_rate with the number of the tick
+    fn fixed_update(&mut self, tick: u64, dt: f32);
+    // once per frame on the window thread before render, e.g. to sample input
+    fn update(&mut self, _ui: &Ui, _dt: f32) {}
+    // once per frame on the window thread
+    fn render(&mut self, ui: &Ui, renderer: &mut MyRenderer);
+    // seconds per tick, asked again every frame so it can change at runtime
+    fn tick_rate(&self) -> f32 {
+        TICK_RATE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    // fixed updates run inside the frame, a stalled window stalls the game
+    SingleThreaded,
+    // fixed updates run on their own thread and keep going while the window is stuck
+    FixedUpdateThread,
+}
+
+
+impl MyRenderer
+{
+    pub fn run_game<G: Game>(self, game: G, mode: LoopMode)
+    {
+        match mode {
+            LoopMode::SingleThreaded => {
+                let mut game = game;
+                let mut timestep = FixedTimestep::new(game.tick_rate());
+                self.run(move |_run, ui, renderer, _sz| {
+                    game.update(ui, ui.io().delta_time);
+                    timestep.set_dt(game.tick_rate());
+                    timestep.update();
+                    while let Some(tick) = timestep.next_tick() {
+                        game.fixed_update(tick, timestep.dt());
+                    }
+                    game.render(ui, renderer);
+                });
+            }
+            LoopMode::FixedUpdateThread => {
+                let game = Arc::new(Mutex::new(game));
+                let sim_game = game.clone();
+                thread::spawn(move || {
+                    let mut timestep = FixedTimestep::new(sim_game.lock().unwrap().tick_rate());
+                    loop {
+                        timestep.update();
+                        while let Some(tick) = timestep.next_tick() {
+                            let mut game = sim_game.lock().unwrap();
+                            game.fixed_update(tick, timestep.dt());
+                            timestep.set_dt(game.tick_rate());
+                        }
+                        thread::sleep(timestep.time_until_next_step().max(Duration::from_millis(1)));
+                    }
+                });
+                self.run(move |_run, ui, renderer, _sz| {
+                    let mut game = game.lock().unwrap();
+                    game.update(ui, ui.io().delta_time);
+                    game.render(ui, renderer);
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// entity ids carry the generation of their slot in the high bits. A slot gets the next
+// generation every time it is freed, so the id of a despawned entity never matches whatever
+// reuses the slot and late messages about it are recognised as stale
+use std::collections::VecDeque;
+use crate::ecs::Entity;
+
+
+pub const ENTITY_INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << ENTITY_INDEX_BITS) - 1;
+const GENERATION_MASK: u32 = u32::MAX >> ENTITY_INDEX_BITS;
+// never handed out, stands for no entity
+pub const INVALID_ENTITY: Entity = u32::MAX;
+
+
+pub fn entity_index(entity: Entity) -> u32
+{
+    entity & INDEX_MASK
+}
+pub fn entity_generation(entity: Entity) -> u32
+{
+    entity >> ENTITY_INDEX_BITS
+}
+pub fn make_entity(index: u32, generation: u32) -> Entity
+{
+    ((generation & GENERATION_MASK) << ENTITY_INDEX_BITS) | (index & INDEX_MASK)
+}
+
+// generations wrap around, anything up to half the range behind counts as older
+fn is_older(generation: u32, than: u32) -> bool
+{
+    let behind = than.wrapping_sub(generation) & GENERATION_MASK;
+    behind != 0 && behind <= GENERATION_MASK / 2
+}
+
+
+#[derive(Default)]
+pub struct IdAllocator
+{
+    // current generation of every slot that was ever used
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    // oldest freed slot first so a slot is reused as late as possible
+    free: VecDeque<u32>,
+}
+
+impl IdAllocator {
+    pub fn new() -> IdAllocator
+    {
+        IdAllocator::default()
+    }
+    pub fn allocate(&mut self) -> Entity
+    {
+        // claimed slots can still be in the queue
+        let index = match self.free.iter().position(|i| !self.alive[*i as usize]) {
+            Some(position) => {
+                let index = self.free[position];
+                self.free.drain(..=position);
+                index
+            }
+            None => {
+                self.free.clear();
+                let index = self.generations.len() as u32;
+                // the last index is left out so INVALID_ENTITY is never handed out
+                assert!(index < INDEX_MASK, "out of entity ids");
+                self.generations.push(0);
+                self.alive.push(false);
+                index
+            }
+        };
+        self.alive[index as usize] = true;
+        make_entity(index, self.generations[index as usize])
+    }
+    // marks an id that was handed out somewhere else (e.g. by the server) as alive.
+    // Fails for stale ids and when the slot is alive already
+    pub fn claim(&mut self, entity: Entity) -> bool
+    {
+        let index = entity_index(entity) as usize;
+        if index == INDEX_MASK as usize || self.is_stale(entity) || self.alive_at(entity).is_some() {
+            return false;
+        }
+        if index >= self.generations.len() {
+            self.generations.resize(index + 1, 0);
+            self.alive.resize(index + 1, false);
+        }
+        self.generations[index] = entity_generation(entity);
+        self.alive[index] = true;
+        true
+    }
+    // returns false for ids that are not alive, including stale ones
+    pub fn free(&mut self, entity: Entity) -> bool
+    {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        let index = entity_index(entity) as usize;
+        self.alive[index] = false;
+        self.generations[index] = (self.generations[index] + 1) & GENERATION_MASK;
+        self.free.push_back(index as u32);
+        true
+    }
+    pub fn is_alive(&self, entity: Entity) -> bool
+    {
+        self.alive_at(entity) == Some(entity)
+    }
+    // the id that is alive in the slot of entity, whatever its generation
+    pub fn alive_at(&self, entity: Entity) -> Option<Entity>
+    {
+        let index = entity_index(entity) as usize;
+        if index < self.generations.len() && self.alive[index] {
+            return Some(make_entity(index as u32, self.generations[index]));
+        }
+        None
+    }
+    // the slot was freed or handed out again since this id was used
+    pub fn is_stale(&self, entity: Entity) -> bool
+    {
+        let index = entity_index(entity) as usize;
+        index < self.generations.len() && is_older(entity_generation(entity), self.generations[index])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_slots_come_back_with_the_next_generation()
+    {
+        let mut ids = IdAllocator::new();
+        let a = ids.allocate();
+        let b = ids.allocate();
+        assert_ne!(a, b);
+        assert!(ids.free(a));
+        assert!(!ids.free(a));
+        assert!(ids.is_stale(a));
+
+        let c = ids.allocate();
+        assert_eq!(entity_index(c), entity_index(a));
+        assert_eq!(entity_generation(c), entity_generation(a) + 1);
+        assert!(ids.is_alive(c) && ids.is_alive(b));
+        assert!(!ids.is_alive(a) && ids.is_stale(a));
+        assert!(!ids.is_stale(c));
+    }
+
+    #[test]
+    fn oldest_freed_slot_is_reused_first()
+    {
+        let mut ids = IdAllocator::new();
+        let a = ids.allocate();
+        let b = ids.allocate();
+        ids.free(b);
+        ids.free(a);
+        assert_eq!(entity_index(ids.allocate()), entity_index(b));
+        assert_eq!(entity_index(ids.allocate()), entity_index(a));
+        assert_eq!(entity_index(ids.allocate()), 2);
+    }
+
+    #[test]
+    fn generation_wraps_around()
+    {
+        let mut ids = IdAllocator::new();
+        let first = ids.allocate();
+        let mut id = first;
+        for _ in 0..GENERATION_MASK {
+            assert!(ids.free(id));
+            id = ids.allocate();
+            assert_eq!(entity_index(id), entity_index(first));
+        }
+        assert_eq!(entity_generation(id), GENERATION_MASK);
+        assert!(ids.free(id));
+        let wrapped = ids.allocate();
+        assert_eq!(wrapped, first);
+        assert!(ids.is_alive(wrapped));
+        assert!(ids.is_stale(id));
+        assert!(!ids.is_stale(wrapped));
+    }
+
+    #[test]
+    fn never_hands_out_the_invalid_entity()
+    {
+        assert_eq!(entity_index(INVALID_ENTITY), INDEX_MASK);
+        let mut ids = IdAllocator::new();
+        assert!(!ids.claim(INVALID_ENTITY));
+        assert!(!ids.is_alive(INVALID_ENTITY));
+    }
+
+    #[test]
+    fn claimed_ids_detect_stale_ones()
+    {
+        let mut ids = IdAllocator::new();
+        let old = make_entity(5, 3);
+        assert!(ids.claim(old));
+        assert!(!ids.claim(old));
+        assert!(ids.free(old));
+        assert!(ids.is_stale(old));
+        assert!(!ids.claim(old));
+
+        let new = make_entity(5, 4);
+        assert!(!ids.is_stale(new));
+        assert!(ids.claim(new));
+        assert_eq!(ids.alive_at(old), Some(new));
+        // the slot was never used
+        assert!(!ids.is_stale(make_entity(6, 0)));
+        assert_ne!(entity_index(ids.allocate()), 5);
+    }
+}
@@ -0,0 +1,33 @@
+// every binary logs through tracing. Readable lines go to stdout, filtered by RUST_LOG
+// (e.g. RUST_LOG=debug or RUST_LOG=server=trace), info and above by default.
+// With a log file every event is also written there as one JSON object per line,
+// together with the fields of the spans it happened in, e.g. the connection
+use std::sync::Mutex;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+
+const DEFAULT_LOG_FILTER: &str = "info";
+
+
+fn log_filter() -> EnvFilter
+{
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER))
+}
+
+// call once at startup, the file is appended to
+pub fn init_logging(json_file: Option<&str>) -> Result<(), String>
+{
+    let json = match json_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .map_err(|e| format!("could not open the log file {}: {}", path, e))?;
+            Some(fmt::layer().json().with_writer(Mutex::new(file)).with_filter(log_filter()))
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(log_filter()))
+        .with(json)
+        .try_init()
+        .map_err(|e| e.to_string())
+}
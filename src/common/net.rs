@@ -0,0 +1,273 @@
+use std::io::{self, prelude::*};
+use std::net::TcpStream;
+use bincode::Options;
+use tracing::debug;
+use crate::game::NetworkMessages;
+use crate::crypto::*;
+use crate::compression::*;
+
+
+// the rcon admin port of the server, a line based text protocol where every
+// response ends with a line holding only RCON_END_OF_RESPONSE
+pub const DEFAULT_RCON_PORT: u16 = 7879;
+pub const RCON_END_OF_RESPONSE: &str = ".";
+// the rcon client reads the password from here if it is not given on the command line
+pub const RCON_PASSWORD_ENV: &str = "RCON_PASSWORD";
+
+
+// largest message either side accepts. Every message goes over the wire as a frame:
+// the payload size as little endian u32 followed by the bincode encoded message
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+pub const FRAME_HEADER_SIZE: usize = 4;
+// an encrypted frame is a bit larger than the message in it
+pub const MAX_FRAME_SIZE: usize = MAX_MESSAGE_SIZE + MAX_MESSAGE_SIZE / 64;
+
+// fixed int encoding like bincode::serialize, but decoding never reads or allocates past the limit
+fn bincode_options() -> impl bincode::Options
+{
+    bincode::options()
+        .with_fixint_encoding()
+        .with_limit(MAX_MESSAGE_SIZE as u64)
+        .reject_trailing_bytes()
+}
+
+// the whole frame, ready to be written
+pub fn encode_message(msg: &NetworkMessages) -> Vec<u8>
+{
+    let payload = bincode_options().serialize(msg).unwrap();
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+// decodes the payload of exactly one frame
+pub fn decode_message(payload: &[u8]) -> io::Result<NetworkMessages>
+{
+    bincode_options().deserialize(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// size of the message on the wire
+pub fn encoded_size(msg: &NetworkMessages) -> usize
+{
+    FRAME_HEADER_SIZE + bincode_options().serialized_size(msg).unwrap() as usize
+}
+
+pub fn send_message<W: Write>(stream: &mut W, msg: &NetworkMessages) -> io::Result<()>
+{
+    stream.write_all(&encode_message(msg))
+}
+
+
+// collects the bytes of a stream until complete messages can be decoded,
+// messages can be split across reads and several can arrive in one read
+#[derive(Default)]
+pub struct MessageReader {
+    buffer: Vec<u8>,
+    // start of the first frame that was not decoded yet
+    start: usize,
+    // set after an encryption handshake, every frame after it is decrypted
+    cipher: Option<ReceiveCipher>,
+    // every frame after Welcome has the compression flag
+    compressed: bool,
+}
+
+impl MessageReader {
+    pub fn new() -> MessageReader
+    {
+        MessageReader::default()
+    }
+    pub fn set_cipher(&mut self, cipher: ReceiveCipher)
+    {
+        self.cipher = Some(cipher);
+    }
+    pub fn set_compressed(&mut self, compressed: bool)
+    {
+        self.compressed = compressed;
+    }
+
+    // blocks until data arrives, a closed connection or undecodable data is returned as error.
+    // Every message comes with the size of its frame on the wire, like MessageWriter reports it
+    pub fn read_from<R: Read>(&mut self, stream: &mut R) -> io::Result<Vec<(NetworkMessages, usize)>>
+    {
+        let mut data = [0u8; 4096];
+        let size = stream.read(&mut data)?;
+        if size == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        self.push_bytes(&data[..size])
+    }
+
+    // blocks until one message arrived and leaves everything after it in the buffer,
+    // needed while connecting as the frames after a handshake are encrypted
+    pub fn read_message<R: Read>(&mut self, stream: &mut R) -> io::Result<NetworkMessages>
+    {
+        loop {
+            if let Some((msg, _)) = self.next_message()? {
+                return Ok(msg);
+            }
+            let mut data = [0u8; 4096];
+            let size = stream.read(&mut data)?;
+            if size == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            self.buffer.drain(..self.start);
+            self.start = 0;
+            self.buffer.extend_from_slice(&data[..size]);
+        }
+    }
+
+    // the buffer never holds more than one incomplete frame, a frame announcing
+    // more than MAX_FRAME_SIZE is an error before any of it is buffered
+    pub fn push_bytes(&mut self, data: &[u8]) -> io::Result<Vec<(NetworkMessages, usize)>>
+    {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        self.buffer.extend_from_slice(data);
+
+        let mut messages = Vec::new();
+        while let Some(msg) = self.next_message()? {
+            messages.push(msg);
+        }
+        Ok(messages)
+    }
+
+    // decodes the next complete frame of the buffer
+    fn next_message(&mut self) -> io::Result<Option<(NetworkMessages, usize)>>
+    {
+        loop {
+            let rest = &self.buffer[self.start..];
+            if rest.len() < FRAME_HEADER_SIZE {
+                return Ok(None);
+            }
+            let size = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if size > MAX_FRAME_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too large", size)));
+            }
+            if rest.len() < FRAME_HEADER_SIZE + size {
+                // the rest of the message has not arrived yet
+                return Ok(None);
+            }
+            let mut payload = &rest[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + size];
+            let decrypted;
+            if let Some(cipher) = &mut self.cipher {
+                decrypted = cipher.open(payload)?;
+                payload = &decrypted;
+            }
+            let decompressed;
+            if self.compressed {
+                decompressed = decompress_payload(payload)?;
+                payload = &decompressed;
+            }
+            let msg = decode_message(payload)?;
+            self.start += FRAME_HEADER_SIZE + size;
+            if !matches!(msg, NetworkMessages::InvalidMessage) {
+                return Ok(Some((msg, FRAME_HEADER_SIZE + size)));
+            }
+            debug!(size, "dropping a message that could not be decoded");
+        }
+    }
+}
+
+
+// writes whole frames to a stream, encrypted once a handshake set a cipher
+pub struct MessageWriter<W> {
+    pub stream: W,
+    cipher: Option<SendCipher>,
+    compressed: bool,
+}
+
+impl<W: Write> MessageWriter<W> {
+    pub fn new(stream: W) -> MessageWriter<W>
+    {
+        MessageWriter { stream, cipher: None, compressed: false }
+    }
+    pub fn set_cipher(&mut self, cipher: SendCipher)
+    {
+        self.cipher = Some(cipher);
+    }
+    pub fn set_compressed(&mut self, compressed: bool)
+    {
+        self.compressed = compressed;
+    }
+    // a frame from encode_message, so a broadcast only serializes once.
+    // Returns the number of bytes written
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<usize>
+    {
+        let compressed;
+        let frame = if self.compressed {
+            compressed = compress_frame(frame);
+            &compressed
+        }
+        else {
+            frame
+        };
+        match &mut self.cipher {
+            Some(cipher) => {
+                let sealed = cipher.seal(frame);
+                self.stream.write_all(&sealed)?;
+                Ok(sealed.len())
+            }
+            None => {
+                self.stream.write_all(frame)?;
+                Ok(frame.len())
+            }
+        }
+    }
+    pub fn send(&mut self, msg: &NetworkMessages) -> io::Result<usize>
+    {
+        self.write_frame(&encode_message(msg))
+    }
+}
+
+
+// what a client asks for when connecting
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions
+{
+    pub encrypt: bool,
+    pub compress: bool,
+    // hex public key the server has to present, any key is accepted when empty
+    pub server_key: String,
+    pub user: String,
+    pub token: String,
+}
+
+// a connection that went through Hello, the optional handshake, Welcome and Authenticate,
+// the answer to Authenticate has not been read yet
+pub struct Connection
+{
+    pub reader: MessageReader,
+    pub writer: MessageWriter<TcpStream>,
+    // the public key the server presented, None without encryption
+    pub server_key: Option<Vec<u8>>,
+    pub compressed: bool,
+}
+
+pub fn connect_to_server(stream: &TcpStream, options: &ConnectOptions) -> io::Result<Connection>
+{
+    let mut stream = stream.try_clone()?;
+    let mut reader = MessageReader::new();
+    let mut writer = MessageWriter::new(stream.try_clone()?);
+    writer.send(&NetworkMessages::Hello{ encrypt: options.encrypt, compress: options.compress })?;
+    let mut server_key = None;
+    if options.encrypt {
+        let (send, receive, key) = initiate_handshake(&mut stream, &mut reader)?;
+        if !options.server_key.is_empty() && from_hex(&options.server_key).as_ref() != Some(&key) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("the server presented the unknown key {}", to_hex(&key))));
+        }
+        reader.set_cipher(receive);
+        writer.set_cipher(send);
+        server_key = Some(key);
+    }
+    let compressed = match reader.read_message(&mut stream)? {
+        NetworkMessages::Welcome{ compress } => compress,
+        // e.g. the address is banned
+        NetworkMessages::Kicked{ reason } => return Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason)),
+        msg => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected Welcome, got {}", msg.name()))),
+    };
+    reader.set_compressed(compressed);
+    writer.set_compressed(compressed);
+    writer.send(&NetworkMessages::Authenticate{ user: options.user.clone(), token: options.token.clone() })?;
+    Ok(Connection { reader, writer, server_key, compressed })
+}
@@ -0,0 +1,165 @@
+// software rasterizer for canvases, renders the same picture as the window without a gpu or even a display.
+// Meant for screenshots and golden image tests: render_world -> check_golden
+use std::path::Path;
+use image::{ImageError, Rgba, RgbaImage};
+use crate::atlas::SpriteAtlas;
+use crate::camera::Camera;
+use crate::canvas::*;
+use crate::ecs::World;
+
+
+// set to regenerate the golden images instead of comparing against them
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+
+fn to_pixel(m: &[[f32; 4]; 4], pos: &[f32; 2], size: [f32; 2]) -> [f32; 2]
+{
+    // glium matrices are column major
+    let clip_x = m[0][0] * pos[0] + m[1][0] * pos[1] + m[3][0];
+    let clip_y = m[0][1] * pos[0] + m[1][1] * pos[1] + m[3][1];
+    [(clip_x + 1.0f32) * 0.5f32 * size[0], (1.0f32 - clip_y) * 0.5f32 * size[1]]
+}
+
+fn texel(atlas: &SpriteAtlas, x: i64, y: i64) -> [f32; 4]
+{
+    let [w, h] = atlas.size();
+    let x = x.clamp(0, w as i64 - 1) as usize;
+    let y = y.clamp(0, h as i64 - 1) as usize;
+    let i = (y * w as usize + x) * 4;
+    let p = &atlas.pixels()[i..i + 4];
+    [p[0] as f32 / 255.0f32, p[1] as f32 / 255.0f32, p[2] as f32 / 255.0f32, p[3] as f32 / 255.0f32]
+}
+
+// linear filtering like the gl sampler, texel centers sit at half coordinates
+fn sample(atlas: &SpriteAtlas, uv: [f32; 2]) -> [f32; 4]
+{
+    let [w, h] = atlas.size();
+    let x = uv[0] * w as f32 - 0.5f32;
+    let y = uv[1] * h as f32 - 0.5f32;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let a = texel(atlas, x0, y0);
+    let b = texel(atlas, x0 + 1, y0);
+    let c = texel(atlas, x0, y0 + 1);
+    let d = texel(atlas, x0 + 1, y0 + 1);
+    let mut out = [0.0f32; 4];
+    for i in 0..4 {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        out[i] = top + (bottom - top) * fy;
+    }
+    out
+}
+
+fn to_rgba8(color: &[f32; 4]) -> Rgba<u8>
+{
+    Rgba(color.map(|c| (c.clamp(0.0f32, 1.0f32) * 255.0f32 + 0.5f32) as u8))
+}
+
+
+// every quad of the canvas in order, blended like Blend::alpha_blending
+pub fn render_canvas(canvas: &Canvas, width: u32, height: u32) -> RgbaImage
+{
+    let mut img = RgbaImage::from_pixel(width, height, to_rgba8(&CLEAR_COLOR));
+    let size = [width as f32, height as f32];
+    let m = canvas.view_matrix();
+    let atlas = canvas.atlas();
+
+    // the canvas only ever produces axis aligned quads, vertex 0 and 2 are opposite corners
+    for quad in canvas.vertices().chunks_exact(4) {
+        let a = to_pixel(m, &quad[0].position, size);
+        let b = to_pixel(m, &quad[2].position, size);
+        if a[0] == b[0] || a[1] == b[1] {
+            continue;
+        }
+        let color = quad[0].color;
+        // pixels whose center is inside the quad, like the gl rasterization rules
+        let px_start = (a[0].min(b[0]) - 0.5f32).ceil().max(0.0f32) as u32;
+        let px_end = ((a[0].max(b[0]) - 0.5f32).ceil().max(0.0f32) as u32).min(width);
+        let py_start = (a[1].min(b[1]) - 0.5f32).ceil().max(0.0f32) as u32;
+        let py_end = ((a[1].max(b[1]) - 0.5f32).ceil().max(0.0f32) as u32).min(height);
+
+        for py in py_start..py_end {
+            let ty = (py as f32 + 0.5f32 - a[1]) / (b[1] - a[1]);
+            let v = quad[0].uv[1] + (quad[2].uv[1] - quad[0].uv[1]) * ty;
+            for px in px_start..px_end {
+                let tx = (px as f32 + 0.5f32 - a[0]) / (b[0] - a[0]);
+                let u = quad[0].uv[0] + (quad[2].uv[0] - quad[0].uv[0]) * tx;
+                let t = sample(atlas, [u, v]);
+                let src = [t[0] * color[0], t[1] * color[1], t[2] * color[2], t[3] * color[3]];
+                let dst = img.get_pixel(px, py).0.map(|c| c as f32 / 255.0f32);
+                let sa = src[3];
+                let out = [
+                    src[0] * sa + dst[0] * (1.0f32 - sa),
+                    src[1] * sa + dst[1] * (1.0f32 - sa),
+                    src[2] * sa + dst[2] * (1.0f32 - sa),
+                    src[3] * sa + dst[3] * (1.0f32 - sa),
+                ];
+                img.put_pixel(px, py, to_rgba8(&out));
+            }
+        }
+    }
+    img
+}
+
+// what a client with this camera would see of the world, without the ui
+pub fn render_world(world: &World, camera: &Camera, width: u32, height: u32) -> RgbaImage
+{
+    let mut camera = camera.clone();
+    camera.set_viewport([width as f32, height as f32]);
+    let mut canvas = Canvas::new();
+    canvas.set_view_matrix(camera.view_matrix());
+    canvas.draw_world(world);
+    render_canvas(&canvas, width, height)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageDiff
+{
+    pub differing_pixels: u64,
+    pub max_channel_diff: u8,
+}
+
+impl ImageDiff
+{
+    pub fn within(&self, max_channel_diff: u8) -> bool
+    {
+        self.max_channel_diff <= max_channel_diff
+    }
+}
+
+// images of different sizes count every pixel as different
+pub fn compare_images(a: &RgbaImage, b: &RgbaImage) -> ImageDiff
+{
+    if a.dimensions() != b.dimensions() {
+        return ImageDiff { differing_pixels: a.width().max(b.width()) as u64 * a.height().max(b.height()) as u64, max_channel_diff: u8::MAX };
+    }
+    let mut diff = ImageDiff { differing_pixels: 0, max_channel_diff: 0 };
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        let d = pa.0.iter().zip(pb.0.iter()).map(|(x, y)| x.abs_diff(*y)).max().unwrap();
+        if d > 0 {
+            diff.differing_pixels += 1;
+            diff.max_channel_diff = diff.max_channel_diff.max(d);
+        }
+    }
+    diff
+}
+
+// compares against the png at path, with UPDATE_GOLDEN set it writes the image there instead.
+// A missing golden image is an error, otherwise a deleted one would always pass
+pub fn check_golden<P: AsRef<Path>>(image: &RgbaImage, path: P) -> Result<ImageDiff, ImageError>
+{
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        image.save(path)?;
+        return Ok(ImageDiff { differing_pixels: 0, max_channel_diff: 0 });
+    }
+    if !path.exists() {
+        let text = format!("missing golden image {}, run with {}=1 to create it", path.display(), UPDATE_GOLDEN_ENV);
+        return Err(ImageError::IoError(std::io::Error::new(std::io::ErrorKind::NotFound, text)));
+    }
+    let golden = image::open(path)?.to_rgba8();
+    Ok(compare_images(image, &golden))
+}
@@ -0,0 +1,152 @@
+use glium::index::PrimitiveType;
+use glium::texture::{RawImage2d, Texture2d};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+use glium::{program, uniform, Blend, Display, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer};
+use crate::atlas::SpriteAtlas;
+use crate::canvas::QuadVertex;
+
+const INITIAL_QUAD_CAPACITY: usize = 1024;
+
+
+// draws every quad of a canvas with a single draw call
+pub struct QuadBatch {
+    program: Program,
+    texture: Texture2d,
+    vertex_buffer: VertexBuffer<QuadVertex>,
+    index_buffer: IndexBuffer<u32>,
+    capacity: usize,
+}
+
+fn quad_indices(quad_count: usize) -> Vec<u32>
+{
+    let mut indices = Vec::with_capacity(quad_count * 6);
+    for i in 0..quad_count as u32 {
+        let b = i * 4;
+        indices.extend_from_slice(&[b, b + 1, b + 2, b + 2, b + 3, b]);
+    }
+    indices
+}
+
+impl QuadBatch {
+    pub fn new(display: &Display) -> QuadBatch
+    {
+        let program = program!(display,
+            140 => {
+                vertex: "
+                    #version 140
+                    uniform mat4 matrix;
+                    in vec2 position;
+                    in vec2 uv;
+                    in vec4 color;
+                    out vec2 v_uv;
+                    out vec4 v_color;
+                    void main() {
+                        v_uv = uv;
+                        v_color = color;
+                        gl_Position = matrix * vec4(position, 0.0, 1.0);
+                    }
+                ",
+                fragment: "
+                    #version 140
+                    uniform sampler2D tex;
+                    in vec2 v_uv;
+                    in vec4 v_color;
+                    out vec4 f_color;
+                    void main() {
+                        f_color = texture(tex, v_uv) * v_color;
+                    }
+                ",
+                outputs_srgb: true,
+            },
+            110 => {
+                vertex: "
+                    #version 110
+                    uniform mat4 matrix;
+                    attribute vec2 position;
+                    attribute vec2 uv;
+                    attribute vec4 color;
+                    varying vec2 v_uv;
+                    varying vec4 v_color;
+                    void main() {
+                        v_uv = uv;
+                        v_color = color;
+                        gl_Position = matrix * vec4(position, 0.0, 1.0);
+                    }
+                ",
+                fragment: "
+                    #version 110
+                    uniform sampler2D tex;
+                    varying vec2 v_uv;
+                    varying vec4 v_color;
+                    void main() {
+                        gl_FragColor = texture2D(tex, v_uv) * v_color;
+                    }
+                ",
+                outputs_srgb: true,
+            },
+        ).expect("Failed to compile the quad shader");
+
+        // plain white until the first atlas is uploaded
+        let white = RawImage2d::from_raw_rgba(vec![255u8; 4], (1, 1));
+        let texture = Texture2d::new(display, white).expect("Failed to create texture");
+
+        QuadBatch {
+            program,
+            texture,
+            vertex_buffer: VertexBuffer::empty_dynamic(display, INITIAL_QUAD_CAPACITY * 4).expect("Failed to create vertex buffer"),
+            index_buffer: IndexBuffer::new(display, PrimitiveType::TrianglesList, &quad_indices(INITIAL_QUAD_CAPACITY)).expect("Failed to create index buffer"),
+            capacity: INITIAL_QUAD_CAPACITY,
+        }
+    }
+
+    pub fn upload_atlas(&mut self, display: &Display, atlas: &SpriteAtlas)
+    {
+        let [w, h] = atlas.size();
+        let image = RawImage2d::from_raw_rgba(atlas.pixels().to_vec(), (w, h));
+        self.texture = Texture2d::new(display, image).expect("Failed to create texture");
+    }
+
+    pub fn draw<S: Surface>(&mut self, display: &Display, target: &mut S, vertices: &[QuadVertex], matrix: &[[f32; 4]; 4])
+    {
+        let quad_count = vertices.len() / 4;
+        if quad_count == 0 {
+            return;
+        }
+        if quad_count > self.capacity {
+            while self.capacity < quad_count {
+                self.capacity *= 2;
+            }
+            self.vertex_buffer = VertexBuffer::empty_dynamic(display, self.capacity * 4).expect("Failed to create vertex buffer");
+            self.index_buffer = IndexBuffer::new(display, PrimitiveType::TrianglesList, &quad_indices(self.capacity)).expect("Failed to create index buffer");
+        }
+        self.vertex_buffer.slice(0..vertices.len()).unwrap().write(vertices);
+
+        let uniforms = uniform! {
+            matrix: *matrix,
+            tex: self.texture.sampled()
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .minify_filter(MinifySamplerFilter::Linear),
+        };
+        let params = DrawParameters {
+            blend: Blend::alpha_blending(),
+            ..Default::default()
+        };
+        let indices = self.index_buffer.slice(0..quad_count * 6).unwrap();
+        target.draw(self.vertex_buffer.slice(0..vertices.len()).unwrap(), indices, &self.program, &uniforms, &params)
+            .expect("Failed to draw quads");
+    }
+}
+
+
+// maps [left, right] x [top, bottom] to clip space, y pointing down like the screen
+pub fn ortho_matrix(left: f32, right: f32, top: f32, bottom: f32) -> [[f32; 4]; 4]
+{
+    let sx = 2.0f32 / (right - left);
+    let sy = 2.0f32 / (top - bottom);
+    [
+        [sx, 0.0, 0.0, 0.0],
+        [0.0, sy, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [-(right + left) * 0.5 * sx, -(top + bottom) * 0.5 * sy, 0.0, 1.0],
+    ]
+}
@@ -2,18 +2,45 @@
 use glium::glutin;
 use glium::glutin::event::{Event, WindowEvent};
 use glium::glutin::event_loop::{ControlFlow, EventLoop};
-use glium::glutin::window::WindowBuilder;
-use glium::{Display, Surface};
+use glium::Surface;
 use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, Ui};
 use imgui_glium_renderer::Renderer;
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
-use std::path::Path;
 use std::time::Instant;
 
-use glutin::event::KeyboardInput;
-
 pub mod game;
 pub use game::*;
+pub mod ecs;
+pub use ecs::*;
+pub mod ids;
+pub use ids::*;
+pub mod net;
+pub use net::*;
+pub mod crypto;
+pub use crypto::*;
+pub mod compression;
+pub use compression::*;
+pub mod timestep;
+pub use timestep::*;
+pub mod clock;
+pub use clock::*;
+pub mod atlas;
+pub use atlas::*;
+pub mod camera;
+pub use camera::*;
+pub mod canvas;
+pub use canvas::*;
+pub mod offscreen;
+pub mod game_loop;
+pub use game_loop::*;
+pub mod diagnostics;
+pub use diagnostics::*;
+pub mod logging;
+pub use logging::*;
+mod quad;
+pub use quad::ortho_matrix;
+use quad::QuadBatch;
+use std::ops::{Deref, DerefMut};
 
 
 
@@ -24,20 +51,32 @@ struct System {
     imgui: Context,
     platform: WinitPlatform,
     renderer: Renderer,
-    font_size: f32,
 }
 
+// the window system is taken out when running, what stays behind is handed to the frame callback for drawing.
+// All the drawing functions live on the Canvas it derefs to
 pub struct MyRenderer
 {
-    system: System,
+    system: Option<System>,
+    display: glium::Display,
+    quads: QuadBatch,
+    canvas: Canvas,
 }
 
+impl Deref for MyRenderer {
+    type Target = Canvas;
+    fn deref(&self) -> &Canvas {
+        &self.canvas
+    }
+}
+impl DerefMut for MyRenderer {
+    fn deref_mut(&mut self) -> &mut Canvas {
+        &mut self.canvas
+    }
+}
 
 
 
-pub trait Updater {
-    fn update(&mut self, ui: &Ui, screen_sz: &[f32; 2]);
-}
 
 impl MyRenderer
 {
@@ -98,17 +137,21 @@ impl MyRenderer
             },
         ]);
         let renderer = Renderer::init(&mut imgui, &display).expect("Failed to initialize renderer");
-        MyRenderer{ system: System{
-                renderer: renderer,
-                imgui: imgui,
-                event_loop: event_loop,
-                display: display,
-                platform: platform,
-                font_size: font_size,
-            }
+        let quads = QuadBatch::new(&display);
+        MyRenderer{
+            system: Some(System{
+                renderer,
+                imgui,
+                event_loop,
+                display: display.clone(),
+                platform,
+            }),
+            display,
+            quads,
+            canvas: Canvas::new(),
         }
     }
-    pub fn run<F: FnMut(&mut bool, &mut Ui, &[f32; 2]) + 'static>(mut self, mut run_ui: F)
+    pub fn run<F: FnMut(&mut bool, &mut Ui, &mut MyRenderer, &[f32; 2]) + 'static>(mut self, mut run_ui: F)
     {
         let System {
             event_loop,
@@ -116,8 +159,7 @@ impl MyRenderer
             mut imgui,
             mut platform,
             mut renderer,
-            ..
-        } = self.system;
+        } = self.system.take().unwrap();
         let mut last_frame = Instant::now();
 
         event_loop.run(move |event, _, control_flow| match event {
@@ -141,15 +183,20 @@ impl MyRenderer
                 let sz = display.get_framebuffer_dimensions();
                 let screen_sz = [sz.0 as f32, sz.1 as f32];
 
-                run_ui(&mut run, &mut ui, &screen_sz);
-               
+                run_ui(&mut run, &mut ui, &mut self, &screen_sz);
+
                 if !run {
                     *control_flow = ControlFlow::Exit;
                 }
 
                 let gl_window = display.gl_window();
                 let mut target = display.draw();
-                target.clear_color_srgb(0.2, 0.2, 0.2, 1.0);
+                target.clear_color_srgb(CLEAR_COLOR[0], CLEAR_COLOR[1], CLEAR_COLOR[2], CLEAR_COLOR[3]);
+                if self.canvas.atlas_mut().take_dirty() {
+                    self.quads.upload_atlas(&display, self.canvas.atlas());
+                }
+                self.quads.draw(&display, &mut target, self.canvas.vertices(), self.canvas.view_matrix());
+                self.canvas.clear();
                 platform.prepare_render(&ui, gl_window.window());
                 let draw_data = ui.render();
                 renderer
@@ -167,13 +214,9 @@ impl MyRenderer
             }
         })
     }
-    pub fn add_quad(&mut self, pos_start: &[f32; 2], pos_size: &[f32; 2], uv_start: &[f32; 2], uv_size: &[f32; 2], base_color: &[f32; 4])
-    {
-        
-    }
-    pub fn add_player(&mut self, player: &Player)
+    pub fn display(&self) -> &glium::Display
     {
-        self.add_quad(&player.pos, &[100.0, 100.0], &[0.0, 0.0f32], &[0.0, 0.0f32], &player.col);
+        &self.display
     }
 
 
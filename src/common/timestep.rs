@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+
+// how many ticks are simulated at most per update before the rest of the backlog is dropped
+pub const MAX_CATCH_UP_STEPS: u32 = 5;
+
+
+// fixed step scheduler driven by the monotonic clock, every simulated step gets a tick number.
+// tick 0 is the initial state, the first simulated step is tick 1. The numbers are local to
+// each process, only the ticks of the server are sent over the network
+pub struct FixedTimestep
+{
+    step: Duration,
+    last: Instant,
+    accumulator: Duration,
+    pending: u32,
+    tick: u64,
+}
+
+impl FixedTimestep
+{
+    pub fn new(step_secs: f32) -> FixedTimestep
+    {
+        FixedTimestep {
+            step: Duration::from_secs_f32(step_secs),
+            last: Instant::now(),
+            accumulator: Duration::ZERO,
+            pending: 0,
+            tick: 0,
+        }
+    }
+
+    // accumulates the time since the last call and returns how many steps are due
+    pub fn update(&mut self) -> u32
+    {
+        let now = Instant::now();
+        self.accumulator += now.saturating_duration_since(self.last);
+        self.last = now;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            if self.pending < MAX_CATCH_UP_STEPS {
+                self.pending += 1;
+            }
+        }
+        self.pending
+    }
+    // consumes one due step and returns its tick number
+    pub fn next_tick(&mut self) -> Option<u64>
+    {
+        if self.pending == 0 {
+            return None;
+        }
+        self.pending -= 1;
+        self.tick += 1;
+        Some(self.tick)
+    }
+
+    // the last simulated tick
+    pub fn tick(&self) -> u64
+    {
+        self.tick
+    }
+    pub fn dt(&self) -> f32
+    {
+        self.step.as_secs_f32()
+    }
+    pub fn set_dt(&mut self, step_secs: f32)
+    {
+        self.step = Duration::from_secs_f32(step_secs);
+    }
+    // how long until the next step is due, as of the last update
+    pub fn time_until_next_step(&self) -> Duration
+    {
+        self.step.saturating_sub(self.accumulator)
+    }
+}
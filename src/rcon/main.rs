@@ -0,0 +1,120 @@
+use std::io::{self, prelude::*, BufReader};
+use std::net::TcpStream;
+
+extern crate common;
+use common::*;
+
+
+const USAGE: &str = "usage: rcon [--address <host:port>] [--password <password>] [command ...]
+without a command it reads commands from stdin, the password can also be set in RCON_PASSWORD";
+
+
+struct RconClient
+{
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl RconClient {
+    fn connect(address: &str, password: &str) -> io::Result<RconClient>
+    {
+        let stream = TcpStream::connect(address)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut client = RconClient { stream, reader };
+        let response = client.request(&format!("auth {}", password))?;
+        if !response.starts_with("ok") {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, response));
+        }
+        Ok(client)
+    }
+    // sends one line and returns the response without the end marker
+    fn request(&mut self, line: &str) -> io::Result<String>
+    {
+        self.stream.write_all(format!("{}\n", line.trim()).as_bytes())?;
+        let mut response = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            if line.trim_end() == RCON_END_OF_RESPONSE {
+                break;
+            }
+            response.push_str(&line);
+        }
+        Ok(response.trim_end().to_string())
+    }
+}
+
+struct Options
+{
+    address: String,
+    password: String,
+    // runs only this command when not empty
+    command: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String>
+{
+    let mut address = format!("127.0.0.1:{}", DEFAULT_RCON_PORT);
+    let mut password = std::env::var(RCON_PASSWORD_ENV).ok();
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--address" => address = args.next().ok_or("missing value for --address")?,
+            "--password" => password = Some(args.next().ok_or("missing value for --password")?),
+            "--help" | "-h" => return Err(String::new()),
+            _ => command.push(arg),
+        }
+    }
+    let password = password.ok_or("no password given")?;
+    Ok(Options { address, password, command })
+}
+
+fn run(options: Options) -> Result<(), String>
+{
+    let Options { address, password, command } = options;
+    let mut client = RconClient::connect(&address, &password).map_err(|e| format!("could not connect to {}: {}", address, e))?;
+
+    if !command.is_empty() {
+        let response = client.request(&command.join(" ")).map_err(|e| e.to_string())?;
+        println!("{}", response);
+        if !response.starts_with("ok") {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(());
+        }
+        match line.trim() {
+            "" => continue,
+            "quit" | "exit" => return Ok(()),
+            line => println!("{}", client.request(line).map_err(|e| e.to_string())?),
+        }
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
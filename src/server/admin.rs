@@ -0,0 +1,196 @@
+use std::net::{IpAddr, Shutdown};
+use imgui::*;
+use common::*;
+use tracing::warn;
+use crate::{ServerData, ServerPlayerState};
+
+
+// everything an admin can do to a player, from the admin panel or remotely
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand
+{
+    Kick(Entity),
+    // kicks the player and refuses further connections from its address
+    Ban(Entity),
+    Teleport(Entity, [f32; 2]),
+    Recolor(Entity, [f32; 4]),
+    Freeze(Entity, bool),
+}
+
+impl AdminCommand {
+    pub fn player(&self) -> Entity
+    {
+        match *self {
+            AdminCommand::Kick(id) | AdminCommand::Ban(id) | AdminCommand::Teleport(id, _)
+                | AdminCommand::Recolor(id, _) | AdminCommand::Freeze(id, _) => id,
+        }
+    }
+}
+
+// ui state of the admin window
+pub struct AdminPanel
+{
+    selected: Option<Entity>,
+    teleport_pos: [f32; 2],
+    color: [f32; 4],
+}
+
+impl AdminPanel {
+    pub fn new() -> AdminPanel
+    {
+        AdminPanel {
+            selected: None,
+            teleport_pos: [GAME_AREA_WIDTH * 0.5f32, GAME_AREA_HEIGHT * 0.5f32],
+            color: [1.0, 1.0, 1.0, 1.0f32],
+        }
+    }
+}
+
+
+impl ServerData {
+    // returns a short description of what happened
+    pub fn run_admin_command(&mut self, cmd: &AdminCommand) -> Result<String, String>
+    {
+        // e.g. an id copied from an old list, it may belong to another player by now
+        if self.world.lock().unwrap().is_stale(cmd.player()) {
+            return Err(format!("player {} has left, the id is stale", cmd.player()));
+        }
+        match *cmd {
+            AdminCommand::Kick(id) => {
+                self.kick(id, "kicked by an admin")?;
+                Ok(format!("kicked player {}", id))
+            }
+            AdminCommand::Ban(id) => {
+                let addr = self.player_addr(id)?;
+                self.banned.lock().unwrap().insert(addr);
+                self.kick(id, "banned by an admin")?;
+                Ok(format!("banned player {} ({})", id, addr))
+            }
+            AdminCommand::Teleport(id, pos) => {
+                // clamp keeps NaN, which would be replicated to every client
+                if !pos.iter().all(|v| v.is_finite()) {
+                    return Err("the position has to be finite".to_string());
+                }
+                let mut world = self.world.lock().unwrap();
+                let p = world.get_mut::<Player>(id).ok_or_else(|| format!("no player {}", id))?;
+                p.pos = [pos[0].clamp(0.0f32, GAME_AREA_WIDTH - 10.0f32), pos[1].clamp(0.0f32, GAME_AREA_HEIGHT - 10.0f32)];
+                Ok(format!("teleported player {} to ({:.1}, {:.1})", id, p.pos[0], p.pos[1]))
+            }
+            AdminCommand::Recolor(id, col) => {
+                if !col.iter().all(|v| v.is_finite()) {
+                    return Err("the colour has to be finite".to_string());
+                }
+                let mut world = self.world.lock().unwrap();
+                let p = world.get_mut::<Player>(id).ok_or_else(|| format!("no player {}", id))?;
+                p.col = col.map(|c| c.clamp(0.0f32, 1.0f32));
+                Ok(format!("recoloured player {}", id))
+            }
+            AdminCommand::Freeze(id, frozen) => {
+                {
+                    let mut world = self.world.lock().unwrap();
+                    let state = world.get_mut::<ServerPlayerState>(id).ok_or_else(|| format!("no player {}", id))?;
+                    state.frozen = frozen;
+                }
+                self.send_to(id, &NetworkMessages::SetFrozen{ frozen });
+                Ok(format!("{} player {}", if frozen { "froze" } else { "unfroze" }, id))
+            }
+        }
+    }
+
+    fn player_addr(&self, id: Entity) -> Result<IpAddr, String>
+    {
+        let world = self.world.lock().unwrap();
+        world.get::<ServerPlayerState>(id).map(|s| s.addr.ip()).ok_or_else(|| format!("no player {}", id))
+    }
+
+    // tells the client why and closes the connection, the reader thread of the
+    // connection notices and removes the player as if it had left
+    fn kick(&mut self, id: Entity, reason: &str) -> Result<(), String>
+    {
+        self.send_to(id, &NetworkMessages::Kicked{ reason: reason.to_string() });
+        let all_streams = self.all_write_streams.lock().unwrap();
+        let stream_data = all_streams.iter().find(|s| s.id == id).ok_or_else(|| format!("no connection for player {}", id))?;
+        let _ = stream_data.writer.stream.shutdown(Shutdown::Both);
+        Ok(())
+    }
+
+    pub fn draw_admin(&mut self, ui: &Ui)
+    {
+        let mut commands = Vec::new();
+        {
+            let world = self.world.lock().unwrap();
+            let panel = &mut self.admin_panel;
+            if panel.selected.is_some_and(|id| !world.contains(id)) {
+                panel.selected = None;
+            }
+            Window::new("Admin")
+               .size([420.0, 320.0], Condition::FirstUseEver)
+               .position([400.0, 20.0], Condition::FirstUseEver)
+               .build(ui, || {
+                   ui.columns(5, "players", true);
+                   for name in ["Player", "Address", "RTT", "Input", "State"] {
+                       ui.text(name);
+                       ui.next_column();
+                   }
+                   ui.separator();
+                   for (id, state) in world.iter::<ServerPlayerState>() {
+                       let input = world.get::<PlayerInput>(id).copied().unwrap_or_default();
+                       let selected = panel.selected == Some(id);
+                       if Selectable::new(format!("{}", id)).selected(selected).span_all_columns(true).build(ui) {
+                           panel.selected = Some(id);
+                           if let Some(p) = world.get::<Player>(id) {
+                               panel.teleport_pos = p.pos;
+                               panel.color = p.col;
+                           }
+                       }
+                       ui.next_column();
+                       ui.text(state.addr.to_string());
+                       ui.next_column();
+                       ui.text(state.rtt.map_or("-".to_string(), |rtt| format!("{:.1}ms", rtt * 1000.0)));
+                       ui.next_column();
+                       ui.text(format!("#{} ({:.2}, {:.2})", input.cur_sequence_id, input.left_right, input.up_down));
+                       ui.next_column();
+                       ui.text(if state.frozen { "frozen" } else { "" });
+                       ui.next_column();
+                   }
+                   ui.columns(1, "players", false);
+                   ui.separator();
+
+                   let id = match panel.selected {
+                       Some(id) => id,
+                       None => {
+                           ui.text("select a player");
+                           return;
+                       }
+                   };
+                   if ui.button("Kick") {
+                       commands.push(AdminCommand::Kick(id));
+                   }
+                   ui.same_line();
+                   if ui.button("Ban IP") {
+                       commands.push(AdminCommand::Ban(id));
+                   }
+                   ui.same_line();
+                   let mut frozen = world.get::<ServerPlayerState>(id).is_some_and(|s| s.frozen);
+                   if ui.checkbox("Frozen", &mut frozen) {
+                       commands.push(AdminCommand::Freeze(id, frozen));
+                   }
+                   Drag::new("##teleport_pos").range(0.0f32, GAME_AREA_WIDTH).build_array(ui, &mut panel.teleport_pos);
+                   ui.same_line();
+                   if ui.button("Teleport") {
+                       commands.push(AdminCommand::Teleport(id, panel.teleport_pos));
+                   }
+                   ColorEdit::new("##color", &mut panel.color).build(ui);
+                   ui.same_line();
+                   if ui.button("Recolour") {
+                       commands.push(AdminCommand::Recolor(id, panel.color));
+                   }
+               });
+        }
+        for cmd in commands {
+            if let Err(e) = self.run_admin_command(&cmd) {
+                warn!(command = ?cmd, "admin command failed: {}", e);
+            }
+        }
+    }
+}
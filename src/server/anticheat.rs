@@ -0,0 +1,235 @@
+// checks the messages of one connection in its reader thread before they reach the tick.
+// A connection can only speak for the player it was given on connect, input sequence ids
+// have to keep increasing and the number of messages per second is limited.
+// Messages that break a rule are dropped, too many of them close the connection
+use std::fmt;
+use common::*;
+use crate::config::MAX_TICK_HZ;
+
+
+// one input per tick at the highest tick rate plus pongs, clock requests and shots
+const MAX_MESSAGES_PER_SEC: f64 = MAX_TICK_HZ as f64 * 2.0f64;
+// how many messages can arrive at once, e.g. after a lag spike of the client
+const MAX_MESSAGE_BURST: f64 = MAX_TICK_HZ as f64;
+// how far the newest sequence id can move forward in one message
+const MAX_SEQUENCE_JUMP: u32 = 32;
+// dropped messages after which the connection is closed
+pub const MAX_VIOLATIONS: u32 = 50;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation
+{
+    // the message carried the id of another player
+    WrongPlayer{ claimed: Entity },
+    SequenceNotIncreasing{ last: u32, got: u32 },
+    SequenceJump{ last: u32, got: u32 },
+    TooManyInputs(usize),
+    // NaN or infinite numbers
+    InvalidValue,
+    RateLimited,
+    // a message only the server sends
+    UnexpectedMessage(&'static str),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::WrongPlayer{ claimed } => write!(f, "sent input for player {}", claimed),
+            Violation::SequenceNotIncreasing{ last, got } => write!(f, "sequence id {} after {}", got, last),
+            Violation::SequenceJump{ last, got } => write!(f, "sequence id jumped from {} to {}", last, got),
+            Violation::TooManyInputs(n) => write!(f, "{} inputs in one message", n),
+            Violation::InvalidValue => write!(f, "invalid number"),
+            Violation::RateLimited => write!(f, "too many messages"),
+            Violation::UnexpectedMessage(name) => write!(f, "unexpected {}", name),
+        }
+    }
+}
+
+
+pub struct ConnectionGuard
+{
+    id: Entity,
+    // newest input sequence id that was accepted, the client starts at 1
+    last_sequence_id: u32,
+    last_fire_sequence_id: u32,
+    // token bucket of the rate limit
+    tokens: f64,
+    last_refill: f64,
+    violations: u32,
+}
+
+impl ConnectionGuard {
+    pub fn new(id: Entity, now: f64) -> ConnectionGuard
+    {
+        ConnectionGuard {
+            id,
+            last_sequence_id: 0,
+            last_fire_sequence_id: 0,
+            tokens: MAX_MESSAGE_BURST,
+            last_refill: now,
+            violations: 0,
+        }
+    }
+    pub fn violations(&self) -> u32
+    {
+        self.violations
+    }
+    // Ok when the message can be passed on to the tick
+    pub fn check(&mut self, msg: &NetworkMessages, now: f64) -> Result<(), Violation>
+    {
+        let result = self.rate_limit(now).and_then(|_| self.check_message(msg));
+        if result.is_err() {
+            self.violations += 1;
+        }
+        result
+    }
+
+    fn rate_limit(&mut self, now: f64) -> Result<(), Violation>
+    {
+        self.tokens = (self.tokens + (now - self.last_refill) * MAX_MESSAGES_PER_SEC).min(MAX_MESSAGE_BURST);
+        self.last_refill = now;
+        if self.tokens < 1.0f64 {
+            return Err(Violation::RateLimited);
+        }
+        self.tokens -= 1.0f64;
+        Ok(())
+    }
+
+    fn check_player(&self, claimed: Entity) -> Result<(), Violation>
+    {
+        if claimed != self.id {
+            return Err(Violation::WrongPlayer{ claimed });
+        }
+        Ok(())
+    }
+
+    fn check_message(&mut self, msg: &NetworkMessages) -> Result<(), Violation>
+    {
+        match msg {
+            NetworkMessages::ClientInput{ inputs } => {
+                if inputs.len() > INPUT_REDUNDANCY {
+                    return Err(Violation::TooManyInputs(inputs.len()));
+                }
+                for input in inputs {
+                    self.check_player(input.id)?;
+                    if !input.left_right.is_finite() || !input.up_down.is_finite() {
+                        return Err(Violation::InvalidValue);
+                    }
+                }
+                // the redundant copies are sent oldest first
+                for pair in inputs.windows(2) {
+                    if pair[1].cur_sequence_id <= pair[0].cur_sequence_id {
+                        return Err(Violation::SequenceNotIncreasing{ last: pair[0].cur_sequence_id, got: pair[1].cur_sequence_id });
+                    }
+                }
+                if let Some(newest) = inputs.last() {
+                    let (last, got) = (self.last_sequence_id, newest.cur_sequence_id);
+                    if got <= last {
+                        return Err(Violation::SequenceNotIncreasing{ last, got });
+                    }
+                    if got - last > MAX_SEQUENCE_JUMP {
+                        return Err(Violation::SequenceJump{ last, got });
+                    }
+                    self.last_sequence_id = got;
+                }
+                Ok(())
+            }
+            NetworkMessages::ClientFire(fire) => {
+                self.check_player(fire.id)?;
+                if !fire.dir.iter().all(|d| d.is_finite()) || !fire.view_tick.is_finite() {
+                    return Err(Violation::InvalidValue);
+                }
+                // a shot refers to the newest input the client had sent
+                let got = fire.cur_sequence_id;
+                if got < self.last_fire_sequence_id || got > self.last_sequence_id {
+                    return Err(Violation::SequenceNotIncreasing{ last: self.last_fire_sequence_id, got });
+                }
+                self.last_fire_sequence_id = got;
+                Ok(())
+            }
+            NetworkMessages::Pong{ server_time } if !server_time.is_finite() => Err(Violation::InvalidValue),
+            NetworkMessages::ClockRequest{..} | NetworkMessages::Pong{..} => Ok(()),
+            _ => Err(Violation::UnexpectedMessage(msg.name())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: Entity = 3;
+
+    fn inputs(sequence_ids: &[u32]) -> NetworkMessages
+    {
+        let inputs = sequence_ids.iter().map(|&cur_sequence_id| PlayerInput { id: ID, cur_sequence_id, ..Default::default() }).collect();
+        NetworkMessages::ClientInput{ inputs }
+    }
+    fn fire(cur_sequence_id: u32) -> NetworkMessages
+    {
+        NetworkMessages::ClientFire(FireInput { id: ID, cur_sequence_id, dir: [1.0f32, 0.0f32], ..Default::default() })
+    }
+
+    #[test]
+    fn accepts_increasing_inputs()
+    {
+        let mut guard = ConnectionGuard::new(ID, 0.0f64);
+        assert_eq!(guard.check(&inputs(&[1]), 0.0f64), Ok(()));
+        // the redundant copies of already accepted inputs are fine
+        assert_eq!(guard.check(&inputs(&[1, 2, 3]), 0.0f64), Ok(()));
+        assert_eq!(guard.check(&fire(3), 0.0f64), Ok(()));
+        assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 1.0f64 }, 0.0f64), Ok(()));
+        assert_eq!(guard.violations(), 0);
+    }
+
+    #[test]
+    fn rejects_sequence_regressions_and_jumps()
+    {
+        let mut guard = ConnectionGuard::new(ID, 0.0f64);
+        assert_eq!(guard.check(&inputs(&[5]), 0.0f64), Ok(()));
+        assert_eq!(guard.check(&inputs(&[5]), 0.0f64), Err(Violation::SequenceNotIncreasing{ last: 5, got: 5 }));
+        assert_eq!(guard.check(&inputs(&[4]), 0.0f64), Err(Violation::SequenceNotIncreasing{ last: 5, got: 4 }));
+        assert_eq!(guard.check(&inputs(&[7, 6]), 0.0f64), Err(Violation::SequenceNotIncreasing{ last: 7, got: 6 }));
+        let far = 5 + MAX_SEQUENCE_JUMP + 1;
+        assert_eq!(guard.check(&inputs(&[far]), 0.0f64), Err(Violation::SequenceJump{ last: 5, got: far }));
+        // a shot can not refer to an input that was never sent
+        assert_eq!(guard.check(&fire(6), 0.0f64), Err(Violation::SequenceNotIncreasing{ last: 0, got: 6 }));
+        assert_eq!(guard.violations(), 5);
+        assert_eq!(guard.check(&inputs(&[6]), 0.0f64), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_inputs()
+    {
+        let mut guard = ConnectionGuard::new(ID, 0.0f64);
+        let nan = NetworkMessages::ClientInput{ inputs: vec![PlayerInput { id: ID, cur_sequence_id: 1, up_down: f32::NAN, ..Default::default() }] };
+        assert_eq!(guard.check(&nan, 0.0f64), Err(Violation::InvalidValue));
+        let inf = NetworkMessages::ClientFire(FireInput { id: ID, cur_sequence_id: 0, dir: [f32::INFINITY, 0.0f32], ..Default::default() });
+        assert_eq!(guard.check(&inf, 0.0f64), Err(Violation::InvalidValue));
+        let other = NetworkMessages::ClientInput{ inputs: vec![PlayerInput { id: ID + 1, cur_sequence_id: 1, ..Default::default() }] };
+        assert_eq!(guard.check(&other, 0.0f64), Err(Violation::WrongPlayer{ claimed: ID + 1 }));
+        let ids: Vec<u32> = (1..=INPUT_REDUNDANCY as u32 + 1).collect();
+        assert_eq!(guard.check(&inputs(&ids), 0.0f64), Err(Violation::TooManyInputs(INPUT_REDUNDANCY + 1)));
+        assert_eq!(guard.check(&NetworkMessages::Ping{ server_time: 0.0f64 }, 0.0f64), Err(Violation::UnexpectedMessage("Ping")));
+        assert_eq!(guard.check(&inputs(&[1]), 0.0f64), Ok(()));
+    }
+
+    #[test]
+    fn limits_the_message_rate()
+    {
+        let mut guard = ConnectionGuard::new(ID, 0.0f64);
+        let burst = MAX_MESSAGE_BURST as u32;
+        for _ in 0..burst {
+            assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 0.0f64 }, 0.0f64), Ok(()));
+        }
+        assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 0.0f64 }, 0.0f64), Err(Violation::RateLimited));
+        // the bucket refills over time
+        let later = 2.0f64 / MAX_MESSAGES_PER_SEC;
+        assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 0.0f64 }, later), Ok(()));
+        assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 0.0f64 }, later), Ok(()));
+        assert_eq!(guard.check(&NetworkMessages::Pong{ server_time: 0.0f64 }, later), Err(Violation::RateLimited));
+        assert_eq!(guard.violations(), 2);
+    }
+}
@@ -0,0 +1,139 @@
+// a new connection starts with Hello, the optional encryption handshake, Welcome and Authenticate.
+// Without a key file every user name is let in, with one the token has to be the hex encoded
+// HMAC-SHA256 of the user name under that key, `server --auth-key <file> --issue-token <user>` prints it
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use common::*;
+use crate::JoinContext;
+
+
+// how long a new connection has to get from Hello to the authentication, in total
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_USER_LENGTH: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+
+pub struct AuthKey
+{
+    key: Vec<u8>,
+}
+
+impl AuthKey {
+    // the whole file is the key, surrounding whitespace is ignored
+    pub fn load(path: &str) -> Result<AuthKey, String>
+    {
+        let text = std::fs::read(path).map_err(|e| format!("could not read the auth key {}: {}", path, e))?;
+        let key = text.trim_ascii().to_vec();
+        if key.is_empty() {
+            return Err(format!("the auth key {} is empty", path));
+        }
+        Ok(AuthKey { key })
+    }
+    fn mac(&self, user: &str) -> HmacSha256
+    {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(user.as_bytes());
+        mac
+    }
+    pub fn issue(&self, user: &str) -> String
+    {
+        to_hex(&self.mac(user).finalize().into_bytes())
+    }
+    // constant time, a wrong token does not tell how much of it was right
+    pub fn verify(&self, user: &str, token: &str) -> bool
+    {
+        from_hex(token).is_some_and(|token| self.mac(user).verify_slice(&token).is_ok())
+    }
+}
+
+// the read timeout of a socket applies to every single read, this sets it to what is left until
+// the deadline before each one, so a client trickling in a byte at a time can not stay forever
+struct DeadlineStream<'a>
+{
+    stream: &'a mut TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "authentication timed out"));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for DeadlineStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+pub fn check_user_name(user: &str) -> Result<(), String>
+{
+    if user.is_empty() || user.len() > MAX_USER_LENGTH {
+        return Err(format!("user names have to be 1 to {} characters long", MAX_USER_LENGTH));
+    }
+    if !user.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err("user names can only contain letters, digits, _ and -".to_string());
+    }
+    Ok(())
+}
+
+// everything up to the point where the connection gets a player, returns the user name.
+// The error is meant for the client
+pub fn accept_client(stream: &mut TcpStream, reader: &mut MessageReader, writer: &mut MessageWriter<TcpStream>, ctx: &JoinContext) -> Result<String, String>
+{
+    let user = {
+        let mut stream = DeadlineStream { stream: &mut *stream, deadline: Instant::now() + AUTH_TIMEOUT };
+        accept_until_deadline(&mut stream, reader, writer, ctx)?
+    };
+    stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+    Ok(user)
+}
+
+fn accept_until_deadline(stream: &mut DeadlineStream, reader: &mut MessageReader, writer: &mut MessageWriter<TcpStream>, ctx: &JoinContext) -> Result<String, String>
+{
+    let (encrypt, compress) = match reader.read_message(stream) {
+        Ok(NetworkMessages::Hello{ encrypt, compress }) => (encrypt, compress),
+        Ok(msg) => return Err(format!("expected Hello, got {}", msg.name())),
+        Err(_) => return Err("no hello received".to_string()),
+    };
+    if encrypt {
+        let (send, receive) = accept_handshake(stream, reader, &ctx.noise_key).map_err(|e| format!("handshake failed: {}", e))?;
+        reader.set_cipher(receive);
+        writer.set_cipher(send);
+    }
+    else if ctx.require_encryption {
+        return Err("the server requires encryption".to_string());
+    }
+    let compress = compress && ctx.compression;
+    crate::send_counted(writer, &NetworkMessages::Welcome{ compress }, &ctx.stats).map_err(|e| e.to_string())?;
+    reader.set_compressed(compress);
+    writer.set_compressed(compress);
+    authenticate(stream, reader, ctx.auth_key.as_deref())
+}
+
+// whatever arrives after Authenticate stays in the reader
+fn authenticate(stream: &mut DeadlineStream, reader: &mut MessageReader, key: Option<&AuthKey>) -> Result<String, String>
+{
+    let (user, token) = match reader.read_message(stream) {
+        Ok(NetworkMessages::Authenticate{ user, token }) => (user, token),
+        Ok(msg) => return Err(format!("expected Authenticate, got {}", msg.name())),
+        Err(_) => return Err("no authentication received".to_string()),
+    };
+    check_user_name(&user)?;
+    if key.is_some_and(|key| !key.verify(&user, &token)) {
+        return Err("authentication failed".to_string());
+    }
+    Ok(user)
+}
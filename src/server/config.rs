@@ -0,0 +1,123 @@
+use common::{TICK_RATE, DEFAULT_RCON_PORT};
+use crate::input_buffer::{DEFAULT_INPUT_BUFFER_DEPTH, MAX_INPUT_BUFFER_DEPTH};
+
+
+pub const MIN_TICK_HZ: f32 = 1.0f32;
+pub const MAX_TICK_HZ: f32 = 120.0f32;
+
+const USAGE: &str = "usage: server [--input-buffer-depth <ticks>] [--tickrate <hz>] [--rcon-password <password>] [--rcon-port <port>]
+       [--auth-key <file>] [--issue-token <user>] [--encryption-key <file>] [--require-encryption]
+       [--no-compression] [--log-json <file>]";
+
+
+// command line options of the server
+#[derive(Debug, Clone)]
+pub struct ServerConfig
+{
+    // how many ticks of input are held back per player to absorb jitter
+    pub input_buffer_depth: usize,
+    // simulated ticks per second
+    pub tick_hz: f32,
+    // the admin port is only opened when a password is set
+    pub rcon_password: Option<String>,
+    pub rcon_port: u16,
+    // file with the secret the join tokens are signed with, anyone can join without it
+    pub auth_key: Option<String>,
+    // print the token of this user and exit instead of running the server
+    pub issue_token: Option<String>,
+    // noise key of the server, created when the file does not exist. Without it
+    // there is a new key every start and clients can not pin it
+    pub encryption_key: Option<String>,
+    // clients that do not ask for encryption are refused
+    pub require_encryption: bool,
+    // compress the connections of clients that ask for it
+    pub compression: bool,
+    // every log event is also appended to this file as JSON
+    pub log_json: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            input_buffer_depth: DEFAULT_INPUT_BUFFER_DEPTH,
+            tick_hz: 1.0f32 / TICK_RATE,
+            rcon_password: None,
+            rcon_port: DEFAULT_RCON_PORT,
+            auth_key: None,
+            issue_token: None,
+            encryption_key: None,
+            require_encryption: false,
+            compression: true,
+            log_json: None,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T, String>
+{
+    let value = value.ok_or_else(|| format!("missing value for {}", name))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+impl ServerConfig {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<ServerConfig, String>
+    {
+        let mut config = ServerConfig::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input-buffer-depth" => {
+                    config.input_buffer_depth = parse_value(&arg, args.next())?;
+                    if config.input_buffer_depth > MAX_INPUT_BUFFER_DEPTH {
+                        return Err(format!("{} can be at most {}", arg, MAX_INPUT_BUFFER_DEPTH));
+                    }
+                }
+                "--tickrate" => {
+                    config.tick_hz = parse_value(&arg, args.next())?;
+                    if !(MIN_TICK_HZ..=MAX_TICK_HZ).contains(&config.tick_hz) {
+                        return Err(format!("{} has to be between {} and {}", arg, MIN_TICK_HZ, MAX_TICK_HZ));
+                    }
+                }
+                "--rcon-password" => {
+                    config.rcon_password = Some(parse_value(&arg, args.next())?);
+                }
+                "--rcon-port" => {
+                    config.rcon_port = parse_value(&arg, args.next())?;
+                }
+                "--auth-key" => {
+                    config.auth_key = Some(parse_value(&arg, args.next())?);
+                }
+                "--issue-token" => {
+                    config.issue_token = Some(parse_value(&arg, args.next())?);
+                }
+                "--encryption-key" => {
+                    config.encryption_key = Some(parse_value(&arg, args.next())?);
+                }
+                "--require-encryption" => {
+                    config.require_encryption = true;
+                }
+                "--no-compression" => {
+                    config.compression = false;
+                }
+                "--log-json" => {
+                    config.log_json = Some(parse_value(&arg, args.next())?);
+                }
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        if config.issue_token.is_some() && config.auth_key.is_none() {
+            return Err("--issue-token needs --auth-key".to_string());
+        }
+        Ok(config)
+    }
+    // exits with the usage on bad arguments
+    pub fn from_args() -> ServerConfig
+    {
+        match ServerConfig::parse(std::env::args().skip(1)) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use common::*;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct EntityState
+{
+    // the replicated component kind the entity was recorded from
+    pub kind: ComponentKind,
+    pub pos: [f32; 2],
+}
+
+pub struct HistoryFrame
+{
+    pub tick: u64,
+    // sorted by entity
+    pub entities: Vec<(Entity, EntityState)>,
+}
+
+impl HistoryFrame
+{
+    pub fn get(&self, entity: Entity) -> Option<&EntityState>
+    {
+        self.entities.binary_search_by_key(&entity, |(e, _)| *e).ok().map(|i| &self.entities[i].1)
+    }
+}
+
+
+// ring buffer of the last `capacity` ticks, frame n lives at n % capacity
+pub struct WorldHistory
+{
+    frames: Vec<Option<HistoryFrame>>,
+    newest_tick: Option<u64>,
+}
+
+impl WorldHistory
+{
+    pub fn new(capacity: usize) -> WorldHistory
+    {
+        assert!(capacity > 0);
+        WorldHistory { frames: (0..capacity).map(|_| None).collect(), newest_tick: None }
+    }
+    pub fn record(&mut self, tick: u64, world: &World)
+    {
+        let mut entities: Vec<(Entity, EntityState)> = world.iter::<Player>()
+            .map(|(e, p)| (e, EntityState { kind: Player::KIND, pos: p.pos }))
+            .chain(world.iter::<Projectile>().map(|(e, p)| (e, EntityState { kind: Projectile::KIND, pos: p.pos })))
+            .collect();
+        entities.sort_by_key(|(e, _)| *e);
+        let idx = (tick % self.frames.len() as u64) as usize;
+        self.frames[idx] = Some(HistoryFrame { tick, entities });
+        self.newest_tick = Some(self.newest_tick.map_or(tick, |t| t.max(tick)));
+    }
+
+    pub fn newest_tick(&self) -> Option<u64>
+    {
+        self.newest_tick
+    }
+    pub fn oldest_tick(&self) -> Option<u64>
+    {
+        self.frames.iter().flatten().map(|f| f.tick).min()
+    }
+    pub fn frame(&self, tick: u64) -> Option<&HistoryFrame>
+    {
+        self.frames[(tick % self.frames.len() as u64) as usize].as_ref().filter(|f| f.tick == tick)
+    }
+
+    // where the entity was at the (fractional) tick, interpolated between the two recorded
+    // ticks around it. If the entity only exists in one of them that state is used as is
+    pub fn state_at(&self, entity: Entity, tick: f64) -> Option<EntityState>
+    {
+        if tick.is_nan() || tick < 0.0f64 { return None; }
+        let from_tick = tick.floor() as u64;
+        let t = (tick - from_tick as f64) as f32;
+        let from = self.frame(from_tick).and_then(|f| f.get(entity));
+        let to = self.frame(from_tick + 1).and_then(|f| f.get(entity));
+        match (from, to) {
+            (Some(a), Some(b)) if t > 0.0f32 => Some(EntityState {
+                kind: a.kind,
+                pos: [a.pos[0] + (b.pos[0] - a.pos[0]) * t, a.pos[1] + (b.pos[1] - a.pos[1]) * t],
+            }),
+            (Some(a), _) => Some(*a),
+            (None, Some(b)) if t > 0.0f32 => Some(*b),
+            _ => None,
+        }
+    }
+    pub fn position_at(&self, entity: Entity, tick: f64) -> Option<[f32; 2]>
+    {
+        self.state_at(entity, tick).map(|s| s.pos)
+    }
+
+    // every entity of the given kind that was alive at the tick, interpolated like state_at
+    pub fn all_at(&self, kind: ComponentKind, tick: f64) -> Vec<(Entity, [f32; 2])>
+    {
+        let from_tick = tick.max(0.0f64).floor() as u64;
+        let frame = match self.frame(from_tick).or_else(|| self.frame(from_tick + 1)) {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+        frame.entities.iter()
+            .filter(|(_, s)| s.kind == kind)
+            .filter_map(|(e, _)| self.position_at(*e, tick).map(|pos| (*e, pos)))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_with_player(pos: [f32; 2]) -> (World, Entity)
+    {
+        let mut world = World::new();
+        let id = world.spawn();
+        world.insert(id, Player { id, pos, ..Default::default() });
+        (world, id)
+    }
+    fn move_player(world: &mut World, id: Entity, pos: [f32; 2])
+    {
+        world.get_mut::<Player>(id).unwrap().pos = pos;
+    }
+
+    #[test]
+    fn exact_ticks_return_the_recorded_state()
+    {
+        let (mut world, id) = world_with_player([0.0f32, 0.0f32]);
+        let mut history = WorldHistory::new(8);
+        history.record(1, &world);
+        move_player(&mut world, id, [10.0f32, 20.0f32]);
+        history.record(2, &world);
+        assert_eq!(history.position_at(id, 1.0f64), Some([0.0f32, 0.0f32]));
+        assert_eq!(history.position_at(id, 2.0f64), Some([10.0f32, 20.0f32]));
+        assert_eq!(history.state_at(id, 2.0f64).unwrap().kind, Player::KIND);
+        assert_eq!(history.newest_tick(), Some(2));
+        assert_eq!(history.oldest_tick(), Some(1));
+    }
+
+    #[test]
+    fn fractional_ticks_are_interpolated()
+    {
+        let (mut world, id) = world_with_player([0.0f32, 0.0f32]);
+        let mut history = WorldHistory::new(8);
+        history.record(1, &world);
+        move_player(&mut world, id, [10.0f32, 20.0f32]);
+        history.record(2, &world);
+        assert_eq!(history.position_at(id, 1.25f64), Some([2.5f32, 5.0f32]));
+        assert_eq!(history.all_at(Player::KIND, 1.5f64), vec![(id, [5.0f32, 10.0f32])]);
+        assert!(history.all_at(Projectile::KIND, 1.5f64).is_empty());
+        // past the newest tick there is nothing to interpolate towards
+        assert_eq!(history.position_at(id, 2.5f64), Some([10.0f32, 20.0f32]));
+    }
+
+    #[test]
+    fn ticks_before_the_oldest_one_are_unknown()
+    {
+        let (world, id) = world_with_player([0.0f32, 0.0f32]);
+        let mut history = WorldHistory::new(8);
+        history.record(5, &world);
+        assert_eq!(history.position_at(id, 3.0f64), None);
+        assert_eq!(history.position_at(id, -1.0f64), None);
+        assert_eq!(history.position_at(id, f64::NAN), None);
+        assert!(history.all_at(Player::KIND, 3.0f64).is_empty());
+    }
+
+    #[test]
+    fn old_ticks_are_overwritten_after_capacity()
+    {
+        let (mut world, id) = world_with_player([0.0f32, 0.0f32]);
+        let mut history = WorldHistory::new(4);
+        for tick in 1..=6u64 {
+            move_player(&mut world, id, [tick as f32, 0.0f32]);
+            history.record(tick, &world);
+        }
+        assert_eq!(history.oldest_tick(), Some(3));
+        assert_eq!(history.newest_tick(), Some(6));
+        // 1 and 2 shared their slots with 5 and 6
+        assert!(history.frame(1).is_none() && history.frame(2).is_none());
+        assert_eq!(history.position_at(id, 2.0f64), None);
+        assert_eq!(history.position_at(id, 3.0f64), Some([3.0f32, 0.0f32]));
+        assert_eq!(history.position_at(id, 5.5f64), Some([5.5f32, 0.0f32]));
+    }
+}
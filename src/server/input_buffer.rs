@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use common::*;
+
+
+pub const DEFAULT_INPUT_BUFFER_DEPTH: usize = 2;
+pub const MAX_INPUT_BUFFER_DEPTH: usize = 32;
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputBufferStats
+{
+    // ticks where the buffer had run dry and the last input was repeated
+    pub underflows: u64,
+    // inputs dropped because the buffer grew past twice its depth
+    pub overflows: u64,
+    // sequence ids that never arrived in time
+    pub skipped: u64,
+}
+
+// server only component, a jitter buffer of the inputs of one player keyed by sequence id.
+// Exactly one input is consumed per tick, `depth` inputs are held back to absorb uneven arrival.
+// Redundant copies and inputs older than the last consumed one are dropped
+pub struct InputBuffer
+{
+    pending: BTreeMap<u32, PlayerInput>,
+    depth: usize,
+    last: Option<PlayerInput>,
+    // set at the start and after an underflow until depth inputs are buffered again
+    filling: bool,
+    stats: InputBufferStats,
+}
+
+impl InputBuffer
+{
+    pub fn new(depth: usize) -> InputBuffer
+    {
+        InputBuffer {
+            pending: BTreeMap::new(),
+            depth: depth.min(MAX_INPUT_BUFFER_DEPTH),
+            last: None,
+            filling: true,
+            stats: InputBufferStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, input: PlayerInput)
+    {
+        if self.last.is_some_and(|last| input.cur_sequence_id <= last.cur_sequence_id) {
+            return;
+        }
+        self.pending.insert(input.cur_sequence_id, input);
+        let max_len = (self.depth * 2).max(1);
+        while self.pending.len() > max_len {
+            self.pending.pop_first();
+            self.stats.overflows += 1;
+        }
+    }
+    // the input for this tick, None until the first input arrived
+    pub fn consume(&mut self) -> Option<PlayerInput>
+    {
+        if self.filling && self.pending.len() < self.depth.max(1) {
+            // refilling after an underflow repeats the last input as well
+            if self.last.is_some() {
+                self.stats.underflows += 1;
+            }
+            return self.last;
+        }
+        self.filling = false;
+        match self.pending.pop_first() {
+            Some((seq, input)) => {
+                let expected = self.last.map_or(seq, |last| last.cur_sequence_id + 1);
+                self.stats.skipped += (seq - expected.min(seq)) as u64;
+                self.last = Some(input);
+            }
+            None => {
+                self.stats.underflows += 1;
+                self.filling = true;
+            }
+        }
+        self.last
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.pending.len()
+    }
+    pub fn depth(&self) -> usize
+    {
+        self.depth
+    }
+    pub fn set_depth(&mut self, depth: usize)
+    {
+        self.depth = depth.min(MAX_INPUT_BUFFER_DEPTH);
+    }
+    pub fn stats(&self) -> &InputBufferStats
+    {
+        &self.stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(seq: u32) -> PlayerInput
+    {
+        PlayerInput { cur_sequence_id: seq, ..Default::default() }
+    }
+    fn consume_seq(buffer: &mut InputBuffer) -> Option<u32>
+    {
+        buffer.consume().map(|i| i.cur_sequence_id)
+    }
+
+    #[test]
+    fn waits_for_depth_inputs_before_the_first_one()
+    {
+        let mut buffer = InputBuffer::new(2);
+        assert_eq!(consume_seq(&mut buffer), None);
+        buffer.push(input(1));
+        assert_eq!(consume_seq(&mut buffer), None);
+        buffer.push(input(2));
+        assert_eq!(consume_seq(&mut buffer), Some(1));
+        assert_eq!(buffer.stats().underflows, 0);
+    }
+
+    #[test]
+    fn reorders_inputs_that_arrive_out_of_order()
+    {
+        let mut buffer = InputBuffer::new(2);
+        buffer.push(input(3));
+        buffer.push(input(1));
+        buffer.push(input(2));
+        assert_eq!(consume_seq(&mut buffer), Some(1));
+        assert_eq!(consume_seq(&mut buffer), Some(2));
+        assert_eq!(consume_seq(&mut buffer), Some(3));
+        assert_eq!(buffer.stats().skipped, 0);
+    }
+
+    #[test]
+    fn drops_duplicates_and_already_consumed_inputs()
+    {
+        let mut buffer = InputBuffer::new(1);
+        buffer.push(input(1));
+        buffer.push(input(1));
+        buffer.push(input(2));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(consume_seq(&mut buffer), Some(1));
+        // a redundant copy arriving after it was consumed
+        buffer.push(input(1));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(consume_seq(&mut buffer), Some(2));
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_inputs()
+    {
+        let mut buffer = InputBuffer::new(2);
+        for seq in 1..=6 {
+            buffer.push(input(seq));
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.stats().overflows, 2);
+        assert_eq!(consume_seq(&mut buffer), Some(3));
+    }
+
+    #[test]
+    fn counts_inputs_that_never_arrived()
+    {
+        let mut buffer = InputBuffer::new(1);
+        buffer.push(input(1));
+        assert_eq!(consume_seq(&mut buffer), Some(1));
+        buffer.push(input(4));
+        assert_eq!(consume_seq(&mut buffer), Some(4));
+        assert_eq!(buffer.stats().skipped, 2);
+    }
+
+    #[test]
+    fn refills_after_an_underflow()
+    {
+        let mut buffer = InputBuffer::new(2);
+        buffer.push(input(1));
+        buffer.push(input(2));
+        assert_eq!(consume_seq(&mut buffer), Some(1));
+        assert_eq!(consume_seq(&mut buffer), Some(2));
+        // ran dry, the last input is repeated until depth inputs are buffered again
+        assert_eq!(consume_seq(&mut buffer), Some(2));
+        buffer.push(input(3));
+        assert_eq!(consume_seq(&mut buffer), Some(2));
+        buffer.push(input(4));
+        assert_eq!(consume_seq(&mut buffer), Some(3));
+        assert_eq!(consume_seq(&mut buffer), Some(4));
+        assert_eq!(buffer.stats().underflows, 2);
+    }
+}
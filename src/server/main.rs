@@ -1,74 +1,250 @@
-use std::net::{TcpListener, TcpStream, Shutdown};
+use std::collections::HashSet;
+use std::net::{TcpListener, TcpStream, Shutdown, SocketAddr, IpAddr};
 use std::io::{prelude::*};
 use std::sync::{Arc, Mutex, mpsc, mpsc::Sender, mpsc::Receiver};
-use std::time::{SystemTime};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
+use std::time::Instant;
 
 use imgui::*;
 extern crate common;
 use common::*;
-use glium::glutin::event::KeyboardInput;
-
-struct ServerPlayerInfo
+use tracing::{error, info, info_span, trace, warn, field};
+
+mod history;
+use history::*;
+mod input_buffer;
+use input_buffer::*;
+mod config;
+use config::*;
+mod admin;
+use admin::*;
+mod rcon;
+use rcon::*;
+mod anticheat;
+use anticheat::*;
+mod auth;
+use auth::*;
+
+// enough ticks to rewind by MAX_REWIND_TIME at the highest tick rate and interpolate past it
+const HISTORY_TICKS: usize = (MAX_REWIND_TIME * MAX_TICK_HZ as f64) as usize + 2;
+// seconds between two pings to every client
+const PING_INTERVAL: f64 = 1.0f64;
+
+// server only state of a player, never replicated
+struct ServerPlayerState
 {
-    player: Player,
-    input: PlayerInput,
-    changed: bool,
+    last_fire_time: f64,
+    addr: SocketAddr,
+    // seconds, measured with Ping/Pong
+    rtt: Option<f64>,
+    // set by an admin, the player can neither move nor shoot
+    frozen: bool,
+    // the name the player authenticated with
+    user: String,
 }
 struct ServerStreamData
 {
-    id: u32,
-    stream: TcpStream,
+    id: Entity,
+    writer: MessageWriter<TcpStream>,
 }
 
 
 struct ServerData
 {
-    all_players: Arc<Mutex<Vec<ServerPlayerInfo>>>,
+    world: Arc<Mutex<World>>,
+    replication: Arc<Replication>,
     all_write_streams: Arc<Mutex<Vec<ServerStreamData>>>,
-    receiver: Receiver<NetworkMessages>,
-    last_time: SystemTime,
-    timer: f32,
+    receiver: Receiver<(Entity, NetworkMessages)>,
+    start_time: Instant,
+    history: WorldHistory,
+    pending_despawns: Vec<Entity>,
+    // the last simulated tick, its length and the simulated time up to it
+    tick: u64,
+    dt: f32,
+    time: f64,
+    camera: Camera,
     update_width_tick: bool,
     has_invalid_stream: bool,
+    config: ServerConfig,
+    stats: Arc<Mutex<NetStats>>,
+    tick_timing: TickTiming,
+    last_ping_time: f64,
+    // addresses the accept thread turns away
+    banned: Arc<Mutex<HashSet<IpAddr>>>,
+    admin_panel: AdminPanel,
+    rcon_receiver: Receiver<RconRequest>,
+    // sent to joining clients, kept up to date by the tick
+    settings: Arc<Mutex<ServerSettings>>,
+    shared_tick: Arc<AtomicU64>,
 }
 
 impl ServerData {
+    // every player consumes exactly one buffered input per tick
     fn update_every_positions(&mut self, dt: f32)
     {
-        let mut p_list = self.all_players.lock().unwrap();
-        for p in p_list.as_mut_slice()
+        let mut world = self.world.lock().unwrap();
+        for e in world.entities_with::<InputBuffer>()
         {
-            if p.input.left_right != 0.0f32 || p.input.up_down != 0.0f32 {
-                p.changed = true;
-                p.player.cur_sequence_id = p.input.cur_sequence_id;
+            let buffer = world.get_mut::<InputBuffer>(e).unwrap();
+            buffer.set_depth(self.config.input_buffer_depth);
+            let mut input = match buffer.consume() {
+                Some(input) => input,
+                None => continue,
+            };
+            input.id = e;
+            input.left_right = input.left_right.clamp(-1.0f32, 1.0f32);
+            input.up_down = input.up_down.clamp(-1.0f32, 1.0f32);
+            world.insert(e, input);
+            if !self.update_width_tick || world.get::<ServerPlayerState>(e).is_some_and(|s| s.frozen) {
+                continue;
+            }
+            // get_mut marks the player for replication, a repeated idle input or a player
+            // against the wall changes nothing and should not be sent again
+            let updated = world.get::<Player>(e).map(|p| {
+                let mut updated = *p;
+                updated.cur_sequence_id = input.cur_sequence_id;
+                updated.update(&input, dt);
+                (updated, updated.pos != p.pos || updated.cur_sequence_id != p.cur_sequence_id)
+            });
+            if let Some((updated, true)) = updated {
+                *world.get_mut::<Player>(e).unwrap() = updated;
             }
-            p.player.update(&p.input, dt);
         }
     }
-    fn send_all_changed_positions(&mut self)
+    // the tick rate can change at runtime, so this is summed up instead of derived from the tick
+    fn sim_time(&self) -> f64
+    {
+        self.time
+    }
+    fn update_projectiles(&mut self, dt: f32)
     {
-        let mut p_list = self.all_players.lock().unwrap();
-        let mut all_positions: Vec<u8> = Vec::new();
-        for p in p_list.as_mut_slice()
+        let mut world = self.world.lock().unwrap();
+        for e in world.entities_with::<Projectile>()
         {
-            if p.changed {
-                let msg = NetworkMessages::Position(Player { id: p.player.id, cur_sequence_id: p.player.cur_sequence_id, pos: p.player.pos, col: p.player.col });
-                let mut v = bincode::serialize(&msg).unwrap();
-                all_positions.append(&mut v);
-                p.changed = false;
+            let proj = world.get_mut::<Projectile>(e).unwrap();
+            if !proj.update(dt) {
+                world.despawn(e);
+                self.pending_despawns.push(e);
+                continue;
+            }
+            let proj = *proj;
+            let target = world.iter::<Player>().find(|(id, p)| *id != proj.owner && proj.hits(&p.pos)).map(|(id, _)| id);
+            if let Some(target) = target {
+                resolve_hit(&mut world, proj.owner, target);
+                world.despawn(e);
+                self.pending_despawns.push(e);
             }
         }
-        if all_positions.len() > 0 {
-            let mut all_stream = self.all_write_streams.lock().unwrap();
-            for i in 0..all_stream.len() {
-                let stream_data = all_stream.get_mut(i).unwrap();
-                if stream_data.stream.write(&all_positions.as_slice()).is_err() {
-                    self.has_invalid_stream = true;
+    }
+    // the projectile is moved from the time the client saw up to now against the
+    // positions the players had back then, if it survives it is spawned as usual
+    fn handle_fire(&mut self, fire: &FireInput)
+    {
+        let mut world = self.world.lock().unwrap();
+        if world.is_stale(fire.id) {
+            warn!(player = fire.id, "shot from a stale player id");
+            return;
+        }
+        let shooter = match world.get::<Player>(fire.id) {
+            Some(p) => *p,
+            None => return,
+        };
+        // the cooldown is only loosely enforced as the shots can arrive bunched up
+        let now = self.sim_time();
+        let state = world.get_mut::<ServerPlayerState>(fire.id).unwrap();
+        if state.frozen || now - state.last_fire_time < (FIRE_COOLDOWN * 0.5f32) as f64 {
+            return;
+        }
+        let mut proj = match Projectile::new(fire.id, shooter.center(), fire.dir) {
+            Some(proj) => proj,
+            None => return,
+        };
+        state.last_fire_time = now;
+
+        let newest = self.history.newest_tick().unwrap_or(self.tick) as f64;
+        // the history holds more than MAX_REWIND_TIME below the highest tick rate
+        let max_rewind = MAX_REWIND_TIME / self.dt as f64;
+        let oldest = self.history.oldest_tick().map_or(newest, |t| t as f64).max(newest - max_rewind);
+        let mut tick = fire.view_tick.clamp(oldest, newest);
+        while tick < newest {
+            let step = f64::min(1.0f64, newest - tick);
+            tick += step;
+            if !proj.update(step as f32 * self.dt) {
+                return;
+            }
+            let positions = self.history.all_at(Player::KIND, tick);
+            if let Some((target, _)) = positions.iter().find(|(id, pos)| *id != fire.id && proj.hits(pos)) {
+                if world.contains(*target) {
+                    resolve_hit(&mut world, fire.id, *target);
                 }
+                return;
+            }
+        }
+        let e = world.spawn();
+        world.insert(e, proj);
+    }
+    fn send_to(&mut self, id: Entity, msg: &NetworkMessages)
+    {
+        let mut all_streams = self.all_write_streams.lock().unwrap();
+        if let Some(stream_data) = all_streams.iter_mut().find(|s| s.id == id) {
+            if send_counted(&mut stream_data.writer, msg, &self.stats).is_err() {
+                self.has_invalid_stream = true;
             }
         }
     }
+    fn send_world_update(&mut self)
+    {
+        let updates = {
+            let mut world = self.world.lock().unwrap();
+            self.replication.collect_changes(&mut world)
+        };
+        let mut all_stream = self.all_write_streams.lock().unwrap();
+        // sent every tick even without changes, the clients take the tick of their shots from it
+        if !broadcast(&mut all_stream, &NetworkMessages::WorldUpdate{ tick: self.tick, updates }, &self.stats) {
+            self.has_invalid_stream = true;
+        }
+        for id in self.pending_despawns.drain(..) {
+            if !broadcast(&mut all_stream, &NetworkMessages::DespawnEntity{ id }, &self.stats) {
+                self.has_invalid_stream = true;
+            }
+        }
+    }
+    fn send_pings(&mut self)
+    {
+        let now = clock_seconds(self.start_time);
+        if now - self.last_ping_time < PING_INTERVAL {
+            return;
+        }
+        self.last_ping_time = now;
+        let mut all_stream = self.all_write_streams.lock().unwrap();
+        if !broadcast(&mut all_stream, &NetworkMessages::Ping{ server_time: now }, &self.stats) {
+            self.has_invalid_stream = true;
+        }
+    }
+    fn draw_diagnostics(&mut self, ui: &Ui)
+    {
+        // same lock order as the accept thread, world before stats
+        let world = self.world.lock().unwrap();
+        let mut stats = self.stats.lock().unwrap();
+        stats.update();
+        Window::new("Diagnostics")
+           .size([360.0, 420.0], Condition::FirstUseEver)
+           .position([20.0, 140.0], Condition::FirstUseEver)
+           .build(ui, || {
+               stats.draw(ui);
+               if CollapsingHeader::new("Connections").default_open(true).build(ui) {
+                   for (id, state) in world.iter::<ServerPlayerState>() {
+                       let rtt = state.rtt.map_or("-".to_string(), |rtt| format!("{:.2}ms", rtt * 1000.0));
+                       ui.text(format!("Player {} {} {} RTT {}", id, state.user, state.addr, rtt));
+                   }
+               }
+               if CollapsingHeader::new("Ticks").default_open(true).build(ui) {
+                   ui.text(format!("Tick {} every {:.2}ms", self.tick, self.dt * 1000.0));
+                   self.tick_timing.draw(ui);
+               }
+           });
+    }
     fn remove_invalid_streams(&mut self)
     {
         let mut all_stream = self.all_write_streams.lock().unwrap();
@@ -76,7 +252,7 @@ impl ServerData {
             self.has_invalid_stream = false;
             for i in 0..all_stream.len() {
                 let mut peek_data: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
-                if all_stream.get(i).unwrap().stream.peek(&mut peek_data).is_err() {
+                if all_stream.get(i).unwrap().writer.stream.peek(&mut peek_data).is_err() {
                     all_stream.remove(i);
                     self.has_invalid_stream = true;
                     break;
@@ -86,148 +262,299 @@ impl ServerData {
     }
 }
 
+// the target respawns somewhere else and the shooter scores
+fn resolve_hit(world: &mut World, shooter: Entity, target: Entity)
+{
+    if let Some(p) = world.get_mut::<Player>(target) {
+        p.pos = create_random_player(target).pos;
+    }
+    if let Some(p) = world.get_mut::<Player>(shooter) {
+        p.score += 1;
+    }
+}
+
+fn send_counted<W: Write>(writer: &mut MessageWriter<W>, msg: &NetworkMessages, stats: &Mutex<NetStats>) -> std::io::Result<()>
+{
+    let size = writer.send(msg)?;
+    stats.lock().unwrap().record_sent(msg, size);
+    Ok(())
+}
+
+// serializes the message once and writes it to every stream, returns false if any write failed
+fn broadcast(streams: &mut [ServerStreamData], msg: &NetworkMessages, stats: &Mutex<NetStats>) -> bool
+{
+    let ser_msg = encode_message(msg);
+    let mut all_ok = true;
+    let mut stats = stats.lock().unwrap();
+    for stream_data in streams {
+        match stream_data.writer.write_frame(&ser_msg) {
+            Ok(size) => stats.record_sent(msg, size),
+            Err(_) => all_ok = false,
+        }
+    }
+    all_ok
+}
+
 
 
 
-impl Updater for ServerData {
-    fn update(&mut self, ui: &Ui, screen_sz: &[f32; 2])
+impl Game for ServerData {
+    fn tick_rate(&self) -> f32
     {
-        let cur = SystemTime::now();
-        let dt = cur.duration_since(self.last_time).unwrap();
-        let in_ms = dt.as_secs_f32();
-        self.timer += in_ms;
-        self.last_time = cur;
-
-
-
-        while self.timer > TICK_RATE {
-
-            for msg in &self.receiver.try_recv() {
-                match msg {
-                    NetworkMessages::ClientInputChange(input) => {
-                        let mut p_list = self.all_players.lock().unwrap();
-                        for p in p_list.as_mut_slice()
-                        {
-                            if p.player.id == input.id {
-                                p.input.left_right = f32::min(f32::max(input.left_right, -1.0f32), 1.0f32);
-                                p.input.up_down = f32::min(f32::max(input.up_down, -1.0f32), 1.0f32);
-                                p.input.cur_sequence_id = input.cur_sequence_id;
-                                break;
-                            }
-                        }
-                    }
-                    NetworkMessages::RemovePlayer{id} => {
-                        let mut p_list = self.all_players.lock().unwrap();
-                        for i in 0..p_list.len()
-                        {
-                            if p_list.get(i).unwrap().player.id == *id {
-                                p_list.remove(i);
-                                break;
-                            }
-                        }
-                        let mut all_streams = self.all_write_streams.lock().unwrap();
-
-                        for i in 0..all_streams.len() {
-                            if all_streams.get(i).unwrap().id == *id {
-                                all_streams.remove(i);
-                                break;
-                            }
-                        }
-                        let ser_msg = bincode::serialize(msg).unwrap();
-                        for stream in all_streams.as_mut_slice() {
-                            if stream.stream.write(&ser_msg).is_err() {
-                                self.has_invalid_stream = true;
-                            }
+        1.0f32 / self.config.tick_hz
+    }
+    fn fixed_update(&mut self, tick: u64, dt: f32)
+    {
+        let tick_start = self.tick_timing.begin();
+        self.tick = tick;
+        self.shared_tick.store(tick, Ordering::Relaxed);
+        self.dt = dt;
+        self.time += dt as f64;
+        self.settings.lock().unwrap().tick_dt = dt;
+        self.handle_rcon_requests();
+
+        while let Ok((sender_id, msg)) = self.receiver.try_recv() {
+            // the player of the connection is gone and the id may belong to someone else by now
+            if self.world.lock().unwrap().is_stale(sender_id) {
+                warn!(player = sender_id, message_type = msg.name(), "message for a stale player id");
+                continue;
+            }
+            match msg {
+                NetworkMessages::ClientInput{inputs} => {
+                    let mut world = self.world.lock().unwrap();
+                    if let Some(buffer) = world.get_mut::<InputBuffer>(sender_id) {
+                        for input in inputs {
+                            buffer.push(input);
                         }
                     }
-                    NetworkMessages::AddLocal(player) => {
-                        println!("[WARNING] GOT ADD LOCAL");
+                }
+                NetworkMessages::ClientFire(fire) => {
+                    self.handle_fire(&fire);
+                }
+                NetworkMessages::ClockRequest{client_time, server_receive_time} => {
+                    let response = NetworkMessages::ClockResponse{
+                        client_time,
+                        server_receive_time,
+                        server_send_time: clock_seconds(self.start_time),
+                        server_tick: self.tick,
+                        tick_dt: self.dt,
+                    };
+                    self.send_to(sender_id, &response);
+                }
+                NetworkMessages::Pong{server_time} => {
+                    let rtt = clock_seconds(self.start_time) - server_time;
+                    if let Some(state) = self.world.lock().unwrap().get_mut::<ServerPlayerState>(sender_id) {
+                        state.rtt = Some(rtt);
                     }
-                    NetworkMessages::Position(player) => {
-                        println!("[WARNING] GOT POSITION");
+                }
+                NetworkMessages::RemovePlayer{id} => {
+                    // the generation in the id keeps this from removing a player that got the slot since
+                    if !self.world.lock().unwrap().despawn(id) {
+                        warn!(player = id, "RemovePlayer for a stale player id");
+                        continue;
                     }
-                    NetworkMessages::AddPlayer(player) => {
-                        println!("[WARNING] GOT ADD PLAYER");
+                    info!(player = id, "left the game");
+                    let mut all_streams = self.all_write_streams.lock().unwrap();
+
+                    for i in 0..all_streams.len() {
+                        if all_streams.get(i).unwrap().id == id {
+                            all_streams.remove(i);
+                            break;
+                        }
                     }
-                    _ => {
-                        println!("[WARNING] GOT INVALID?");
+                    if !broadcast(&mut all_streams, &msg, &self.stats) {
+                        self.has_invalid_stream = true;
                     }
-                };
-    
-            }
-            
-            if self.update_width_tick {
-                self.update_every_positions(TICK_RATE);
-            }
+                }
+                // the connection guard lets only client messages through
+                _ => {
+                    warn!(player = sender_id, message_type = msg.name(), "unexpected message from a client");
+                }
+            };
 
-            self.send_all_changed_positions();
-            
-            
-            self.timer -= TICK_RATE;
         }
+
+        self.update_every_positions(dt);
+        self.update_projectiles(dt);
+        self.history.record(tick, &self.world.lock().unwrap());
+
+        self.send_world_update();
+        self.send_pings();
         self.remove_invalid_streams();
-        
-        let p_list = self.all_players.lock().unwrap();
-        let player_size = world_to_screen(&screen_sz, &[PLAYER_SIZE, PLAYER_SIZE]);
-        for p in p_list.as_slice() {
-            let draw_list = ui.get_background_draw_list();
-            let pw = world_to_screen(&screen_sz, &p.player.pos);
-            draw_list.add_rect(pw, [pw[0] + player_size[0], pw[1] + player_size[1]], p.player.col).filled(true).build();
-            
-        }
+        self.tick_timing.end(tick_start);
+    }
+
+    fn render(&mut self, ui: &Ui, renderer: &mut MyRenderer)
+    {
+        self.camera.handle_ui(ui);
+        renderer.set_view_matrix(self.camera.view_matrix());
+
+        let world = self.world.lock().unwrap();
+        renderer.draw_world(&world);
 
+        let mut fit_world = false;
         Window::new("Test Window")
            .size([300.0, 100.0], Condition::FirstUseEver)
-           .build(&ui, || {
+           .build(ui, || {
                ui.text("Test!");
+               fit_world = ui.button("Fit world");
+               ui.separator();
+               let mut depth = self.config.input_buffer_depth as u32;
+               if Slider::new("Input buffer depth", 0, MAX_INPUT_BUFFER_DEPTH as u32).build(ui, &mut depth) {
+                   self.config.input_buffer_depth = depth as usize;
+               }
+               for (id, buffer) in world.iter::<InputBuffer>() {
+                   let stats = buffer.stats();
+                   ui.text(format!(
+                       "Player {}: buffered {}/{} underflows {} overflows {} skipped {}",
+                       id, buffer.len(), buffer.depth(), stats.underflows, stats.overflows, stats.skipped
+                   ));
+               }
                ui.separator();
                let mouse_pos = ui.io().mouse_pos;
+               let mouse_world = self.camera.screen_to_world(&mouse_pos);
                ui.text(format!(
-                   "Mouse Position: ({:.1},{:.1})",
-                   mouse_pos[0], mouse_pos[1]
+                   "Mouse Position: ({:.1},{:.1}) World: ({:.1},{:.1})",
+                   mouse_pos[0], mouse_pos[1], mouse_world[0], mouse_world[1]
                ));
            });
+        drop(world);
+        if fit_world {
+            self.camera.fit_world();
+        }
+        self.draw_diagnostics(ui);
+        self.draw_admin(ui);
 
     }
-   
+
 }
 
 
-fn handle_client(mut stream_data: ServerStreamData, sender: Sender<NetworkMessages>) {
-    
-    loop {
-            let mut data = [0 as u8; 500];
-            match stream_data.stream.read(&mut data) {
-            Ok(size) => {
-                if size > 0 {
-                    let mut cursor = &data[..];
-                    while cursor.len() > 0 {
-                        let msg: Result<NetworkMessages, _> = bincode::deserialize_from(&mut cursor);
-                        if msg.is_ok() {
-                            let real_msg = msg.unwrap();
-                            if !matches!(real_msg, NetworkMessages::InvalidMessage) {
-                                sender.send(real_msg).unwrap();
-                            }
-                        }
-                    }
-                }
-                else {
-                    println!("An error occurred, terminating connection with {}", stream_data.stream.peer_addr().unwrap());
-                    stream_data.stream.shutdown(Shutdown::Both).unwrap();
-                    break;
-                }
-            },
-            _ => {
-                println!("An error occurred, terminating connection with {}", stream_data.stream.peer_addr().unwrap());
-                stream_data.stream.shutdown(Shutdown::Both).unwrap();
+
+// what the accept thread hands to every new connection
+#[derive(Clone)]
+struct JoinContext
+{
+    world: Arc<Mutex<World>>,
+    all_write_streams: Arc<Mutex<Vec<ServerStreamData>>>,
+    stats: Arc<Mutex<NetStats>>,
+    banned: Arc<Mutex<HashSet<IpAddr>>>,
+    sender: Sender<(Entity, NetworkMessages)>,
+    start_time: Instant,
+    input_buffer_depth: usize,
+    auth_key: Option<Arc<AuthKey>>,
+    noise_key: Arc<NoiseKey>,
+    require_encryption: bool,
+    compression: bool,
+    settings: Arc<Mutex<ServerSettings>>,
+    replication: Arc<Replication>,
+    // the newest simulated tick
+    tick: Arc<AtomicU64>,
+}
+
+// runs on the thread of the connection so a client that is slow to authenticate does not
+// hold up everyone else, only authenticated clients get a player
+fn join_client(mut stream: TcpStream, ctx: JoinContext)
+{
+    let addr = match stream.peer_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    let mut writer = match stream.try_clone() {
+        Ok(write_stream) => MessageWriter::new(write_stream),
+        Err(_) => return,
+    };
+    // everything logged for this connection carries the address, and the user and player once known
+    let span = info_span!("connection", %addr, user = field::Empty, player = field::Empty);
+    let _enter = span.enter();
+    info!("new connection");
+    if ctx.banned.lock().unwrap().contains(&addr.ip()) {
+        warn!("refusing banned address");
+        let _ = send_counted(&mut writer, &NetworkMessages::Kicked{ reason: "banned".to_string() }, &ctx.stats);
+        return;
+    }
+    let mut reader = MessageReader::new();
+    let user = match accept_client(&mut stream, &mut reader, &mut writer, &ctx) {
+        Ok(user) => user,
+        Err(e) => {
+            warn!("refusing connection: {}", e);
+            let _ = send_counted(&mut writer, &NetworkMessages::Kicked{ reason: e }, &ctx.stats);
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+    };
+    span.record("user", user.as_str());
+    info!("authenticated");
+
+    let settings = *ctx.settings.lock().unwrap();
+    let new_id = {
+        let mut write_list = ctx.all_write_streams.lock().unwrap();
+        let mut world = ctx.world.lock().unwrap();
+
+        let new_id = world.spawn();
+        let new_player = create_random_player(new_id);
+        world.insert(new_id, new_player);
+        let updates = ctx.replication.snapshot(&world);
+        let tick = ctx.tick.load(Ordering::Relaxed);
+        let state = NetworkMessages::WorldState{ local_id: new_id, tick, updates, map: MapInfo::default(), settings };
+        if send_counted(&mut writer, &state, &ctx.stats).is_err() {
+            world.despawn(new_id);
+            return;
+        }
+
+        world.insert(new_id, PlayerInput { id: new_id, ..Default::default() });
+        world.insert(new_id, InputBuffer::new(ctx.input_buffer_depth));
+        world.insert(new_id, ServerPlayerState { last_fire_time: f64::MIN, addr, rtt: None, frozen: false, user });
+        // the other clients get the new player with the next world update, it is marked as changed
+
+        write_list.push(ServerStreamData{
+            id: new_id,
+            writer,
+        });
+        new_id
+    };
+    span.record("player", new_id);
+    info!("joined the game");
+
+    handle_client(new_id, stream, reader, &ctx);
+}
+
+// the reader can already hold messages that arrived together with the authentication
+fn handle_client(id: Entity, mut stream: TcpStream, mut reader: MessageReader, ctx: &JoinContext) {
+
+    let mut guard = ConnectionGuard::new(id, clock_seconds(ctx.start_time));
+    let mut messages = reader.push_bytes(&[]);
+    'connection: loop {
+        let received = match messages {
+            Ok(messages) => messages,
+            Err(e) => {
+                info!("connection closed: {}", e);
+                let _ = stream.shutdown(Shutdown::Both);
                 break;
             }
-        } { 
-
-         }
+        };
+        for (mut msg, size) in received {
+            trace!(message_type = msg.name(), size, "received");
+            ctx.stats.lock().unwrap().record_received(&msg, size);
+            if let Err(violation) = guard.check(&msg, clock_seconds(ctx.start_time)) {
+                warn!(message_type = msg.name(), %violation, "dropping message");
+                if guard.violations() >= MAX_VIOLATIONS {
+                    warn!(violations = guard.violations(), "broke the rules too often, terminating connection");
+                    let _ = stream.shutdown(Shutdown::Both);
+                    break 'connection;
+                }
+                continue;
+            }
+            // stamped here instead of in the tick loop so waiting for the tick does not count as network delay
+            if let NetworkMessages::ClockRequest{ref mut server_receive_time, ..} = msg {
+                *server_receive_time = clock_seconds(ctx.start_time);
+            }
+            ctx.sender.send((id, msg)).unwrap();
+        }
+        messages = reader.read_from(&mut stream);
     }
-    let msg = NetworkMessages::RemovePlayer { id: stream_data.id };
-    sender.send(msg).unwrap();
+    let msg = NetworkMessages::RemovePlayer { id };
+    ctx.sender.send((id, msg)).unwrap();
 
 }
 
@@ -235,86 +562,90 @@ fn handle_client(mut stream_data: ServerStreamData, sender: Sender<NetworkMessag
 
 fn main() {
 
-    let (sender, receiver) = mpsc::channel::<NetworkMessages>();
-
-    let mut data = ServerData{
-        receiver: receiver,
-        timer: 0.0f32,
+    let config = ServerConfig::from_args();
+    if let Err(e) = init_logging(config.log_json.as_deref()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let auth_key = config.auth_key.as_ref().map(|path| AuthKey::load(path).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    }));
+    if let (Some(user), Some(key)) = (&config.issue_token, &auth_key) {
+        if let Err(e) = check_user_name(user) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        // the token itself is the output, not a log line
+        println!("{}", key.issue(user));
+        return;
+    }
+    let noise_key = match &config.encryption_key {
+        Some(path) => NoiseKey::load_or_create(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }),
+        None => NoiseKey::generate(),
+    };
+    info!("encryption key {}", noise_key.public_hex());
+    let (sender, receiver) = mpsc::channel::<(Entity, NetworkMessages)>();
+    let (rcon_sender, rcon_receiver) = mpsc::channel::<RconRequest>();
+    if let Some(password) = &config.rcon_password {
+        spawn_rcon_listener(config.rcon_port, password.clone(), rcon_sender);
+    }
+    let start_time = Instant::now();
+
+    let data = ServerData{
+        receiver,
+        start_time,
+        tick: 0,
+        dt: 1.0f32 / config.tick_hz,
+        time: 0.0f64,
+        camera: Camera::new([1024.0f32, 768.0f32]),
         update_width_tick: true,
-        last_time: SystemTime::now(),
-        all_players: Arc::new(Mutex::new(Vec::new())),
+        history: WorldHistory::new(HISTORY_TICKS),
+        pending_despawns: Vec::new(),
+        world: Arc::new(Mutex::new(World::new())),
+        replication: Arc::new(create_replication()),
         all_write_streams: Arc::new(Mutex::new(Vec::new())),
         has_invalid_stream: false,
+        config: config.clone(),
+        stats: Arc::new(Mutex::new(NetStats::new())),
+        tick_timing: TickTiming::default(),
+        last_ping_time: f64::MIN,
+        banned: Arc::new(Mutex::new(HashSet::new())),
+        admin_panel: AdminPanel::new(),
+        rcon_receiver,
+        settings: Arc::new(Mutex::new(ServerSettings { tick_dt: 1.0f32 / config.tick_hz, ..Default::default() })),
+        shared_tick: Arc::new(AtomicU64::new(0)),
     };
 
-    let write_stream_copy = data.all_write_streams.clone();
-    let player_list_copy = data.all_players.clone();
+    let ctx = JoinContext {
+        world: data.world.clone(),
+        all_write_streams: data.all_write_streams.clone(),
+        stats: data.stats.clone(),
+        banned: data.banned.clone(),
+        sender,
+        start_time,
+        input_buffer_depth: config.input_buffer_depth,
+        auth_key: auth_key.map(Arc::new),
+        noise_key: Arc::new(noise_key),
+        require_encryption: config.require_encryption,
+        compression: config.compression,
+        settings: data.settings.clone(),
+        replication: data.replication.clone(),
+        tick: data.shared_tick.clone(),
+    };
     thread::spawn(move ||{
         let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
         for stream in listener.incoming() {
             match stream {
-                Ok(mut stream) => {
-                    println!("New connection: {}", stream.peer_addr().unwrap());
-                    
-                    
-                    let mut write_list = write_stream_copy.lock().unwrap();
-                    let mut p_list = player_list_copy.lock().unwrap();
-
-                    let mut new_id = 0;
-                    loop {
-                        let mut found = false;
-                        for s_data in write_list.as_slice() {
-                            let data = s_data;
-                            if data.id == new_id  { found = true; break;}
-                        }
-                        if found {
-                            new_id += 1;
-                        }
-                        else
-                        {
-                            break;
-                        }
-                    }
-
-                    let new_player = create_random_player(new_id);
-                    let msg: NetworkMessages = NetworkMessages::AddLocal(new_player);
-                    let serialized_msg = bincode::serialize(&msg).unwrap();
-                    stream.write(serialized_msg.as_slice()).unwrap();
-                    for p in p_list.as_slice() 
-                    {
-                        let add_player_msg: NetworkMessages = NetworkMessages::AddPlayer(p.player);
-                        let ser_msg = bincode::serialize(&add_player_msg).unwrap();
-                        stream.write(ser_msg.as_slice()).unwrap();
-                    }
-
-                    p_list.push(ServerPlayerInfo { player: Player{ id: new_player.id, cur_sequence_id: 0, pos: new_player.pos, col: new_player.col }, input: PlayerInput { id: new_player.id, cur_sequence_id: 0, up_down: 0.0f32, left_right: 0.0f32 }, changed: false });
-                    let player_add_msg = NetworkMessages::AddPlayer(p_list.last().unwrap().player);
-                    let ser_msg = bincode::serialize(&player_add_msg).unwrap();
-                    for stream in write_list.as_mut_slice()
-                    {
-                        if stream.id == new_player.id { continue; }
-                        if stream.stream.write(ser_msg.as_slice()).is_err() {
-
-                        }
-                    }
-
-
-                    let stream_data = ServerStreamData{
-                        id: new_id,
-                        stream: stream,
-                    };
-                    write_list.push(ServerStreamData{
-                        id: stream_data.id,
-                        stream: stream_data.stream.try_clone().unwrap(),
-                    });
-
-                    let sender_copy = sender.clone();
-                    thread::spawn(move|| {
-                        handle_client(stream_data, sender_copy);
-                    });
+                Ok(stream) => {
+                    let ctx = ctx.clone();
+                    thread::spawn(move || join_client(stream, ctx));
                 }
                 Err(e) => {
-                    println!("Error: {}", e);
+                    error!("could not accept a connection: {}", e);
                     panic!();
                 }
             }
@@ -322,8 +653,5 @@ fn main() {
     });
 
     let r = MyRenderer::new("Server");
-    r.run(move |run, ui, sz| {
-            data.update(&ui, sz);
-        }
-    );
-} 
\ No newline at end of file
+    r.run_game(data, LoopMode::FixedUpdateThread);
+}
@@ -0,0 +1,240 @@
+// remote console: a line based text protocol on a local tcp port.
+// The first line has to be `auth <password>`, after that every line is a command.
+// Every response starts with `ok` or `error: <reason>`, can have more lines and ends with a line
+// holding only RCON_END_OF_RESPONSE
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use common::*;
+use tracing::{info, info_span, warn};
+use crate::admin::AdminCommand;
+use crate::config::{MIN_TICK_HZ, MAX_TICK_HZ};
+use crate::{ServerData, ServerPlayerState};
+
+
+// longest line the server reads, anything longer ends the connection
+const MAX_LINE_LENGTH: u64 = 1024;
+const HELP: &str = "commands:
+list
+stats
+say <message>
+set tickrate <hz>
+kick <id>
+ban <id>
+teleport <id> <x> <y>
+freeze <id>
+unfreeze <id>
+help";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RconCommand
+{
+    List,
+    Stats,
+    Say(String),
+    SetTickRate(f32),
+    Admin(AdminCommand),
+    Help,
+}
+
+// the command and where the tick sends the answer to
+pub type RconRequest = (RconCommand, Sender<Result<String, String>>);
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&str>, name: &str) -> Result<T, String>
+{
+    let arg = arg.ok_or_else(|| format!("missing {}", name))?;
+    arg.parse().map_err(|_| format!("invalid {}: {}", name, arg))
+}
+
+pub fn parse_command(line: &str) -> Result<RconCommand, String>
+{
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut args = rest.split_whitespace();
+    let cmd = match name {
+        "list" => RconCommand::List,
+        "stats" => RconCommand::Stats,
+        "help" => RconCommand::Help,
+        "say" => {
+            if rest.is_empty() {
+                return Err("missing message".to_string());
+            }
+            RconCommand::Say(rest.to_string())
+        }
+        "set" => match args.next() {
+            Some("tickrate") => {
+                let hz: f32 = parse_arg(args.next(), "tick rate")?;
+                if !(MIN_TICK_HZ..=MAX_TICK_HZ).contains(&hz) {
+                    return Err(format!("tick rate has to be between {} and {}", MIN_TICK_HZ, MAX_TICK_HZ));
+                }
+                RconCommand::SetTickRate(hz)
+            }
+            Some(var) => return Err(format!("unknown variable {}", var)),
+            None => return Err("missing variable".to_string()),
+        },
+        "kick" => RconCommand::Admin(AdminCommand::Kick(parse_arg(args.next(), "id")?)),
+        "ban" => RconCommand::Admin(AdminCommand::Ban(parse_arg(args.next(), "id")?)),
+        "teleport" => {
+            let id = parse_arg(args.next(), "id")?;
+            let x: f32 = parse_arg(args.next(), "x")?;
+            let y: f32 = parse_arg(args.next(), "y")?;
+            // f32 parses nan and inf as well
+            if !x.is_finite() || !y.is_finite() {
+                return Err("the position has to be finite".to_string());
+            }
+            RconCommand::Admin(AdminCommand::Teleport(id, [x, y]))
+        }
+        "freeze" => RconCommand::Admin(AdminCommand::Freeze(parse_arg(args.next(), "id")?, true)),
+        "unfreeze" => RconCommand::Admin(AdminCommand::Freeze(parse_arg(args.next(), "id")?, false)),
+        "" => return Err("empty command".to_string()),
+        _ => return Err(format!("unknown command {}, try help", name)),
+    };
+    if args.next().is_some() && !matches!(cmd, RconCommand::Say(_)) {
+        return Err("too many arguments".to_string());
+    }
+    Ok(cmd)
+}
+
+// compares every byte so the time taken does not tell how much of the password was right
+fn password_matches(given: &str, password: &str) -> bool
+{
+    let (a, b) = (given.as_bytes(), password.as_bytes());
+    let mut diff = (a.len() != b.len()) as u8;
+    for i in 0..a.len().max(b.len()) {
+        diff |= a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0);
+    }
+    diff == 0
+}
+
+fn write_response(stream: &mut TcpStream, response: &Result<String, String>) -> std::io::Result<()>
+{
+    let text = match response {
+        Ok(text) if text.is_empty() => "ok\n".to_string(),
+        Ok(text) => format!("ok\n{}\n", text),
+        Err(e) => format!("error: {}\n", e),
+    };
+    stream.write_all(format!("{}{}\n", text, RCON_END_OF_RESPONSE).as_bytes())
+}
+
+// None when the connection was closed or the line was too long
+fn read_line<R: BufRead>(reader: &mut R) -> Option<String>
+{
+    let mut line = String::new();
+    match reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) if !line.ends_with('\n') => None,
+        Ok(_) => Some(line),
+    }
+}
+
+fn handle_rcon_client(mut stream: TcpStream, password: String, sender: Sender<RconRequest>) -> std::io::Result<()>
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let authenticated = read_line(&mut reader)
+        .and_then(|line| line.trim().strip_prefix("auth ").map(|p| password_matches(p, &password)))
+        .unwrap_or(false);
+    if !authenticated {
+        warn!("RCON authentication failed");
+        write_response(&mut stream, &Err("authentication failed".to_string()))?;
+        return Ok(());
+    }
+    write_response(&mut stream, &Ok(String::new()))?;
+
+    while let Some(line) = read_line(&mut reader) {
+        let response = match parse_command(&line) {
+            Ok(cmd) => {
+                info!(command = ?cmd, "RCON command");
+                let (reply_sender, reply) = mpsc::channel();
+                if sender.send((cmd, reply_sender)).is_err() {
+                    break;
+                }
+                reply.recv().unwrap_or_else(|_| Err("server shutting down".to_string()))
+            }
+            Err(e) => Err(e),
+        };
+        write_response(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+// only listens on localhost, remote administration has to go through a tunnel
+pub fn spawn_rcon_listener(port: u16, password: String, sender: Sender<RconRequest>)
+{
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(port, "could not open the rcon port: {}", e);
+            return;
+        }
+    };
+    info!("RCON listening on {}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let password = password.clone();
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let addr = stream.peer_addr().map_or("unknown address".to_string(), |a| a.to_string());
+                let _span = info_span!("rcon", %addr).entered();
+                if let Err(e) = handle_rcon_client(stream, password, sender) {
+                    warn!("RCON connection failed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+
+impl ServerData {
+    pub fn handle_rcon_requests(&mut self)
+    {
+        while let Ok((cmd, reply)) = self.rcon_receiver.try_recv() {
+            let _ = reply.send(self.run_rcon_command(&cmd));
+        }
+    }
+
+    pub fn run_rcon_command(&mut self, cmd: &RconCommand) -> Result<String, String>
+    {
+        match cmd {
+            RconCommand::Help => Ok(HELP.to_string()),
+            RconCommand::List => {
+                let world = self.world.lock().unwrap();
+                let lines: Vec<String> = world.iter::<ServerPlayerState>().map(|(id, state)| {
+                    let p = world.get::<Player>(id).copied().unwrap_or_default();
+                    format!(
+                        "{} {} {} rtt {} score {} pos ({:.1}, {:.1}){}",
+                        id, state.user, state.addr, state.rtt.map_or("-".to_string(), |rtt| format!("{:.1}ms", rtt * 1000.0)),
+                        p.score, p.pos[0], p.pos[1], if state.frozen { " frozen" } else { "" }
+                    )
+                }).collect();
+                Ok(format!("{} players\n{}", lines.len(), lines.join("\n")).trim_end().to_string())
+            }
+            RconCommand::Stats => {
+                let players = self.world.lock().unwrap().entities_with::<ServerPlayerState>().len();
+                let mut stats = self.stats.lock().unwrap();
+                stats.update();
+                Ok(format!(
+                    "tick {} at {:.1} Hz, {} players\nin {:.2} KB/s {:.0} msg/s, {} msgs total\nout {:.2} KB/s {:.0} msg/s, {} msgs total\ntick duration {:.3}ms (max {:.3}ms)",
+                    self.tick, self.config.tick_hz, players,
+                    stats.receive_rate.bytes_per_sec / 1024.0f32, stats.receive_rate.messages_per_sec, stats.received.messages,
+                    stats.send_rate.bytes_per_sec / 1024.0f32, stats.send_rate.messages_per_sec, stats.sent.messages,
+                    self.tick_timing.duration_plot.last(), self.tick_timing.max_duration,
+                ))
+            }
+            RconCommand::Say(text) => {
+                let mut all_streams = self.all_write_streams.lock().unwrap();
+                if !crate::broadcast(&mut all_streams, &NetworkMessages::ServerMessage{ text: text.clone() }, &self.stats) {
+                    self.has_invalid_stream = true;
+                }
+                Ok(String::new())
+            }
+            RconCommand::SetTickRate(hz) => {
+                self.config.tick_hz = *hz;
+                Ok(format!("tick rate set to {} Hz", hz))
+            }
+            RconCommand::Admin(cmd) => self.run_admin_command(cmd),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// renders fixed scenes in software and compares them with the images in tests/golden,
+// `UPDATE_GOLDEN=1 cargo test --test golden` rewrites them after an intended change
+use std::path::PathBuf;
+use common::*;
+use common::offscreen::*;
+
+
+// rounding differences between platforms, anything larger is a real change
+const MAX_CHANNEL_DIFF: u8 = 2;
+
+
+fn golden_path(name: &str) -> PathBuf
+{
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name)
+}
+
+fn scene() -> World
+{
+    let mut world = World::new();
+    let players = [
+        ([200.0f32, 150.0f32], [1.0f32, 0.2f32, 0.2f32, 1.0f32], 0),
+        ([520.0f32, 400.0f32], [0.2f32, 0.6f32, 1.0f32, 1.0f32], 1),
+        ([760.0f32, 220.0f32], [0.3f32, 1.0f32, 0.3f32, 1.0f32], 2),
+    ];
+    for (pos, col, sprite) in players {
+        let id = world.spawn();
+        world.insert(id, Player { id, cur_sequence_id: 0, pos, col, sprite, score: 0 });
+    }
+    let proj = world.spawn();
+    world.insert(proj, Projectile { owner: 0, pos: [400.0f32, 300.0f32], vel: [1.0f32, 0.0f32], time_left: 1.0f32 });
+    world
+}
+
+#[test]
+fn world_matches_golden()
+{
+    let camera = Camera::new([GAME_AREA_WIDTH, GAME_AREA_HEIGHT]);
+    let image = render_world(&scene(), &camera, 320, 240);
+    let diff = check_golden(&image, golden_path("world.png")).unwrap();
+    assert!(diff.within(MAX_CHANNEL_DIFF), "{:?}", diff);
+}